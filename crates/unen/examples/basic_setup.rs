@@ -4,7 +4,7 @@ use unen_runner::prelude::MininalRunner;
 fn main() {
     let _ = create_engine()
         .set_runner(MininalRunner::default())
-        .add_event_handler(LoggerEventHandler)
+        .add_event_handler(LoggerEventHandler::default())
         .start()
         .stop();
 }
@@ -5,7 +5,7 @@ use unen_winit::prelude::WinitRunner;
 fn main() {
     let _ = create_engine()
         .set_runner(WinitRunner::default())
-        .add_event_handler(LoggerEventHandler)
+        .add_event_handler(LoggerEventHandler::default())
         .add_event_handler(RendererEventHandler::default())
         .start()
         .stop();
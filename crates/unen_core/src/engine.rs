@@ -5,8 +5,9 @@ use unen_runner::prelude::{MininalRunner, Runner, RunnerBox};
 ///
 /// Noramlly not used directly by the user.
 /// State transitions are handled via [`StoppedEngine`] and [`StartedEngine`].
-#[derive(Debug, PartialEq, Eq)]
-enum EngineState {
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EngineState {
+    #[default]
     Stopped,
     Started,
 }
@@ -27,6 +28,20 @@ impl Default for EngineData {
     }
 }
 
+impl EngineState {
+    /// Returns the state the engine is in while the given lifecycle event is
+    /// being dispatched.
+    pub fn from_event(event: &EngineEvent) -> Option<Self> {
+        match event {
+            EngineEvent::Starting | EngineEvent::Started | EngineEvent::Stopping => {
+                Some(EngineState::Started)
+            }
+            EngineEvent::Stopped => Some(EngineState::Stopped),
+            EngineEvent::FixedUpdate { .. } | EngineEvent::Update | EngineEvent::Shutdown => None,
+        }
+    }
+}
+
 /// Represents the engine in the "stopped" state.
 ///
 /// From here you can only call [`StoppedEngine::start`] to transition into a
@@ -39,6 +54,7 @@ pub struct StoppedEngine {
 impl StoppedEngine {
     /// Starts the engine, consuming `self` and returning a [`StartedEngine`].
     pub fn start(mut self) -> StartedEngine {
+        let startup = tracing::trace_span!("startup").entered();
         self.data.state = EngineState::Started;
         // We must step since there is no runner yet
         self.runner.emit(EngineEvent::Starting);
@@ -47,6 +63,7 @@ impl StoppedEngine {
         // We must step since there is no runner yet
         self.runner.emit(EngineEvent::Started);
         self.runner.step();
+        drop(startup);
 
         self.runner.run();
 
@@ -83,11 +100,13 @@ pub struct StartedEngine {
 impl StartedEngine {
     /// Stops the engine, consuming `self` and returning a [`StoppedEngine`].
     pub fn stop(mut self) -> StoppedEngine {
+        let _shutdown = tracing::trace_span!("shutdown").entered();
         // We must step since there is no runner anymore
         self.runner.emit(EngineEvent::Stopping);
         self.runner.step();
 
         // We must step since there is no runner anymore
+        self.data.state = EngineState::Stopped;
         self.runner.emit(EngineEvent::Stopped);
         self.runner.step();
 
@@ -2,5 +2,7 @@ mod engine;
 
 /// The prelude.
 pub mod prelude {
-    pub use crate::{engine::create_engine, engine::StartedEngine, engine::StoppedEngine};
+    pub use crate::{
+        engine::create_engine, engine::EngineState, engine::StartedEngine, engine::StoppedEngine,
+    };
 }
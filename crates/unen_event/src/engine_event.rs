@@ -4,7 +4,17 @@ use crate::prelude::Event;
 pub enum EngineEvent {
     Starting,
     Started,
+    /// Emitted at a fixed rate, once per simulation tick, before the
+    /// [`EngineEvent::Update`] of the frame the tick falls in.
+    ///
+    /// The tick is the one networking uses for inputs and snapshots.
+    FixedUpdate {
+        tick: u32,
+    },
     Update,
+    /// Asks the runner to leave its main loop, after which the engine is
+    /// stopped as usual.
+    Shutdown,
     Stopping,
     Stopped,
 }
@@ -2,6 +2,11 @@ use std::any::Any;
 
 pub trait Event: Any + Send + Sync {
     fn as_any(&self) -> &dyn Any;
+
+    /// Type name of the event, used in diagnostics.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
 
 pub struct EventBox(Box<dyn Event>);
@@ -11,6 +16,10 @@ impl EventBox {
         Self(Box::new(event))
     }
 
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
     pub fn downcast_ref<E: Event>(&self) -> Option<&E> {
         self.0.as_any().downcast_ref::<E>()
     }
@@ -1,5 +1,15 @@
-use crate::prelude::EventBox;
+use crate::prelude::{EventBox, EventEmitter};
 
 pub trait EventHandler: Send + Sync {
     fn handle(&mut self, event: &EventBox) -> bool;
+
+    /// Called once when the handler is added to an
+    /// [`crate::prelude::EventManager`], giving it a way to emit events of its
+    /// own.
+    fn attach(&mut self, _emitter: EventEmitter) {}
+
+    /// Type name of the handler, used in diagnostics.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
@@ -1,8 +1,11 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{channel, Receiver, Sender},
+};
 
 use crate::{
     event::Event,
-    prelude::{EventBox, EventHandler},
+    prelude::{EngineEvent, EventBox, EventHandler},
 };
 
 type EventReceiver = Receiver<EventBox>;
@@ -18,27 +21,60 @@ impl EventEmitter {
     }
 }
 
+struct HandlerEntry {
+    handler: Box<dyn EventHandler>,
+    /// Set once the handler panicked, it is skipped from then on.
+    poisoned: bool,
+}
+
 pub struct EventManager {
-    handlers: Vec<Box<dyn EventHandler>>,
+    handlers: Vec<HandlerEntry>,
     receiver: EventReceiver,
     emitter: EventEmitter,
+    shutdown_requested: bool,
 }
 
 impl EventManager {
-    pub fn add_handler<H: EventHandler + 'static>(&mut self, handler: H) {
-        self.handlers.push(Box::new(handler));
+    pub fn add_handler<H: EventHandler + 'static>(&mut self, mut handler: H) {
+        handler.attach(self.get_emitter());
+        self.handlers.push(HandlerEntry {
+            handler: Box::new(handler),
+            poisoned: false,
+        });
     }
 
     pub fn step(&mut self) {
+        let _step = tracing::trace_span!("step").entered();
         while let Ok(event) = self.receiver.try_recv() {
-            for handler in &mut self.handlers {
-                if handler.handle(&event) {
-                    break;
+            let _dispatch = tracing::trace_span!("dispatch", event = event.name()).entered();
+            if let Some(EngineEvent::Shutdown) = event.downcast_ref::<EngineEvent>() {
+                self.shutdown_requested = true;
+            }
+
+            for entry in self.handlers.iter_mut().filter(|entry| !entry.poisoned) {
+                let handler = &mut entry.handler;
+                let _handle = tracing::trace_span!("handle", handler = handler.name()).entered();
+                // A panicking handler must not take the whole loop down with
+                // it, the engine is shut down instead so every other handler
+                // can clean up
+                match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(&event))) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(_) => {
+                        entry.poisoned = true;
+                        self.shutdown_requested = true;
+                    }
                 }
             }
         }
     }
 
+    /// Whether an [`EngineEvent::Shutdown`] was dispatched or a handler
+    /// panicked, in which case the runner should leave its main loop.
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.shutdown_requested
+    }
+
     pub fn get_emitter(&self) -> EventEmitter {
         self.emitter.clone()
     }
@@ -53,6 +89,7 @@ impl Default for EventManager {
             handlers: Vec::new(),
             receiver,
             emitter,
+            shutdown_requested: false,
         }
     }
 }
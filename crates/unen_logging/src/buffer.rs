@@ -0,0 +1,227 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+use unen_event::prelude::Event;
+
+/// A single captured log line.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub timestamp: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>5} {}: {}", self.level, self.target, self.message)
+    }
+}
+
+/// Emitted for every record captured by a [`LogBuffer`] attached to the
+/// logger, so consoles and overlays can display it.
+#[derive(Debug, Clone, Event)]
+pub struct LogEvent {
+    pub record: LogRecord,
+}
+
+struct LogBufferInner {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+    /// Amount of records ever pushed, used as a cursor by readers.
+    total: u64,
+}
+
+/// Bounded in-memory ring buffer holding the most recent log records.
+///
+/// Cheap to clone, every clone refers to the same storage. Once full, the
+/// oldest record is dropped for each new one.
+#[derive(Clone)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<LogBufferInner>>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(LogBufferInner {
+                records: VecDeque::with_capacity(capacity),
+                capacity,
+                total: 0,
+            })),
+        }
+    }
+
+    pub fn push(&self, record: LogRecord) {
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        if inner.capacity == 0 {
+            return;
+        }
+        if inner.records.len() == inner.capacity {
+            inner.records.pop_front();
+        }
+        inner.records.push_back(record);
+        inner.total += 1;
+    }
+
+    /// Returns up to `count` of the newest records, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<LogRecord> {
+        let inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        let skip = inner.records.len().saturating_sub(count);
+        inner.records.iter().skip(skip).cloned().collect()
+    }
+
+    /// Returns the records pushed since `cursor` that are still held, together
+    /// with the cursor to use on the next call.
+    pub fn since(&self, cursor: u64) -> (Vec<LogRecord>, u64) {
+        let inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        let missing = inner.total.saturating_sub(cursor) as usize;
+        let skip = inner.records.len().saturating_sub(missing);
+        let records = inner.records.iter().skip(skip).cloned().collect();
+        (records, inner.total)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .capacity
+    }
+}
+
+impl fmt::Debug for LogBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogBuffer")
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Subscriber layer feeding every enabled event into a [`LogBuffer`].
+pub struct LogBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl LogBufferLayer {
+    pub fn new(buffer: LogBuffer) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        // Records bridged from the `log` crate carry their real target as a
+        // field instead
+        let metadata = event.metadata();
+        let target = visitor
+            .log_target
+            .unwrap_or_else(|| metadata.target().to_string());
+
+        self.buffer.push(LogRecord {
+            timestamp: SystemTime::now(),
+            level: *metadata.level(),
+            target,
+            message: visitor.message,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    log_target: Option<String>,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "log.target" => self.log_target = Some(value.to_string()),
+            name if name.starts_with("log.") => {}
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let fields = std::mem::take(&mut self.message);
+                let _ = write!(self.message, "{value:?}");
+                self.message.push_str(&fields);
+            }
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.message, " {name}={value:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            timestamp: SystemTime::now(),
+            level: Level::INFO,
+            target: "test".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(records: &[LogRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.message.as_str()).collect()
+    }
+
+    #[test]
+    fn drops_oldest_when_full() {
+        let buffer = LogBuffer::new(2);
+        for message in ["a", "b", "c"] {
+            buffer.push(record(message));
+        }
+
+        assert_eq!(
+            messages(&buffer.recent(10)),
+            ["b", "c"],
+            "should only keep the newest records"
+        );
+        assert_eq!(
+            messages(&buffer.recent(1)),
+            ["c"],
+            "should return the newest record"
+        );
+    }
+
+    #[test]
+    fn since_cursor() {
+        let buffer = LogBuffer::new(2);
+        buffer.push(record("a"));
+        let (records, cursor) = buffer.since(0);
+        assert_eq!(messages(&records), ["a"], "should return the first record");
+
+        for message in ["b", "c", "d"] {
+            buffer.push(record(message));
+        }
+        let (records, cursor) = buffer.since(cursor);
+        assert_eq!(
+            messages(&records),
+            ["c", "d"],
+            "should skip records already dropped from the buffer"
+        );
+
+        let (records, _) = buffer.since(cursor);
+        assert!(records.is_empty(), "should have nothing new");
+    }
+}
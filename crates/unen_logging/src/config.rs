@@ -0,0 +1,269 @@
+use thiserror::Error;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    filter::{filter_fn, ParseError},
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::{SubscriberInitExt, TryInitError},
+    EnvFilter, Layer,
+};
+
+use crate::{
+    buffer::{LogBuffer, LogBufferLayer},
+    file::{FileLogConfig, RotatingFileWriter},
+    profiler::{Profiler, ProfilerConfig},
+};
+
+/// Environment variable used to override the configured filter directives.
+pub const LOG_ENV_VAR: &str = "RUST_LOG";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid filter directives: {0}")]
+    InvalidFilter(#[from] ParseError),
+    #[error("a global subscriber is already installed: {0}")]
+    AlreadyInitialized(#[from] TryInitError),
+    #[error("failed to open log file: {0}")]
+    FileOpenFailed(#[from] std::io::Error),
+}
+
+/// Output format of the log lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, one line per record, including span context.
+    #[default]
+    Full,
+    /// Shorter variant of [`LogFormat::Full`].
+    Compact,
+    /// Newline delimited JSON objects, intended for log collectors.
+    Json,
+}
+
+/// Configuration of the global logging subscriber.
+///
+/// Built with chained `with_*` calls and handed to
+/// [`crate::prelude::LoggerEventHandler::new`], which installs it when the
+/// engine is starting.
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    directives: Vec<String>,
+    use_env: bool,
+    format: LogFormat,
+    timestamps: bool,
+    thread_names: bool,
+    ansi: bool,
+    target: bool,
+    file: Option<FileLogConfig>,
+    buffer: Option<LogBuffer>,
+    profiler: Option<(ProfilerConfig, Profiler)>,
+}
+
+/// Keeps the background log writers alive.
+///
+/// Dropping it flushes whatever is still queued, so it must be held until the
+/// engine is stopped.
+#[must_use]
+pub struct LoggingGuard {
+    _file: Option<WorkerGuard>,
+}
+
+impl LoggingConfig {
+    /// Replaces every filter directive with the given comma separated list,
+    /// using the `env_logger` syntax (e.g. `"info,unen_net=debug"`).
+    pub fn with_filter(mut self, filter: impl Into<String>) -> Self {
+        self.directives = vec![filter.into()];
+        self
+    }
+
+    /// Appends a single filter directive (e.g. `"unen_render=trace"`).
+    pub fn with_directive(mut self, directive: impl Into<String>) -> Self {
+        self.directives.push(directive.into());
+        self
+    }
+
+    /// Whether [`LOG_ENV_VAR`] takes precedence over the configured
+    /// directives when it is set. Enabled by default.
+    pub fn with_env(mut self, use_env: bool) -> Self {
+        self.use_env = use_env;
+        self
+    }
+
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub fn with_thread_names(mut self, thread_names: bool) -> Self {
+        self.thread_names = thread_names;
+        self
+    }
+
+    pub fn with_ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    pub fn with_target(mut self, target: bool) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Additionally writes every record to a log file, without ANSI colors.
+    pub fn with_file(mut self, file: FileLogConfig) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Additionally captures every record into the given ring buffer, which
+    /// the logger republishes as [`crate::prelude::LogEvent`]s.
+    pub fn with_buffer(mut self, buffer: LogBuffer) -> Self {
+        self.buffer = Some(buffer);
+        self
+    }
+
+    pub fn buffer(&self) -> Option<&LogBuffer> {
+        self.buffer.as_ref()
+    }
+
+    /// Enables the frame profiler, capturing spans into Chrome trace files on
+    /// request.
+    pub fn with_profiler(mut self, config: ProfilerConfig) -> Self {
+        let profiler = Profiler::new(&config);
+        self.profiler = Some((config, profiler));
+        self
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref().map(|(_, profiler)| profiler)
+    }
+
+    pub fn profiler_config(&self) -> Option<&ProfilerConfig> {
+        self.profiler.as_ref().map(|(config, _)| config)
+    }
+
+    /// Builds the filter from the configured directives, or from
+    /// [`LOG_ENV_VAR`] when allowed and set.
+    pub fn filter(&self) -> Result<EnvFilter, Error> {
+        if self.use_env {
+            if let Ok(directives) = std::env::var(LOG_ENV_VAR) {
+                return Ok(EnvFilter::try_new(directives)?);
+            }
+        }
+
+        Ok(EnvFilter::try_new(self.directives.join(","))?)
+    }
+
+    /// Installs the configured subscriber as the global default.
+    ///
+    /// Fails with [`Error::AlreadyInitialized`] if another subscriber was
+    /// installed before, which is common in tests or when the application
+    /// sets up its own logging.
+    pub fn init(&self) -> Result<LoggingGuard, Error> {
+        // Every output gets its own filter so the profiler can still observe
+        // spans below the configured level
+        let (file_layer, file_guard) = match &self.file {
+            Some(file) => {
+                let (writer, guard) =
+                    tracing_appender::non_blocking(RotatingFileWriter::new(file.clone())?);
+                let layer = self.fmt_layer(writer, false).with_filter(self.filter()?);
+                (Some(layer), Some(guard))
+            }
+            None => (None, None),
+        };
+
+        let buffer_layer = match &self.buffer {
+            Some(buffer) => Some(LogBufferLayer::new(buffer.clone()).with_filter(self.filter()?)),
+            None => None,
+        };
+
+        let profiler_layer = self.profiler().map(|profiler| {
+            profiler
+                .layer()
+                .with_filter(filter_fn(|metadata| metadata.is_span()))
+        });
+
+        tracing_subscriber::registry()
+            .with(
+                self.fmt_layer(std::io::stdout, self.ansi)
+                    .with_filter(self.filter()?),
+            )
+            .with(file_layer)
+            .with(buffer_layer)
+            .with(profiler_layer)
+            .try_init()?;
+
+        Ok(LoggingGuard { _file: file_guard })
+    }
+
+    fn fmt_layer<S, W>(&self, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let layer = fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .with_target(self.target)
+            .with_thread_names(self.thread_names)
+            .with_level(true);
+
+        match (self.format, self.timestamps) {
+            (LogFormat::Full, true) => layer.boxed(),
+            (LogFormat::Full, false) => layer.without_time().boxed(),
+            (LogFormat::Compact, true) => layer.compact().boxed(),
+            (LogFormat::Compact, false) => layer.compact().without_time().boxed(),
+            (LogFormat::Json, true) => layer.json().boxed(),
+            (LogFormat::Json, false) => layer.json().without_time().boxed(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            directives: vec!["info".to_string()],
+            use_env: true,
+            format: LogFormat::default(),
+            timestamps: true,
+            thread_names: false,
+            ansi: true,
+            target: false,
+            file: None,
+            buffer: None,
+            profiler: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_filter() {
+        let config = LoggingConfig::default()
+            .with_env(false)
+            .with_filter("unen_net=[");
+        assert!(
+            matches!(config.filter(), Err(Error::InvalidFilter(_))),
+            "should reject malformed directives"
+        );
+    }
+
+    #[test]
+    fn init_twice() {
+        let config = LoggingConfig::default().with_env(false);
+        let _ = config.init();
+        assert!(
+            matches!(config.init(), Err(Error::AlreadyInitialized(_))),
+            "should report the already installed subscriber"
+        );
+    }
+}
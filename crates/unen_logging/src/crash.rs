@@ -0,0 +1,450 @@
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::Write,
+    fs,
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, ThreadId},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use unen_core::prelude::EngineState;
+use unen_event::prelude::{EngineEvent, EventBox, EventEmitter, EventHandler};
+
+use crate::buffer::LogBuffer;
+
+/// Configuration of the crash reporter.
+#[derive(Debug, Clone)]
+pub struct CrashReportConfig {
+    directory: PathBuf,
+    events: usize,
+    log_lines: usize,
+    log_buffer: Option<LogBuffer>,
+}
+
+impl CrashReportConfig {
+    /// Reports are written as `crash-<unix time>-<pid>-<count>.txt` into
+    /// `directory`.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            events: 32,
+            log_lines: 64,
+            log_buffer: None,
+        }
+    }
+
+    /// Amount of last dispatched events included in the report.
+    pub fn with_events(mut self, events: usize) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Includes the newest `log_lines` records of the given buffer, which
+    /// should also be handed to [`crate::prelude::LoggingConfig::with_buffer`].
+    pub fn with_log_buffer(mut self, log_buffer: LogBuffer, log_lines: usize) -> Self {
+        self.log_buffer = Some(log_buffer);
+        self.log_lines = log_lines;
+        self
+    }
+}
+
+/// Name of a dispatched event and how many times in a row it was seen.
+struct EventEntry {
+    name: &'static str,
+    repeated: usize,
+}
+
+/// Engine information shared between the handler and the panic hook.
+struct CrashContext {
+    state: EngineState,
+    events: VecDeque<EventEntry>,
+    emitter: Option<EventEmitter>,
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Hook installed by a reporter, to recognize it when the reporter is dropped.
+struct InstalledHook {
+    previous: Arc<PanicHook>,
+    /// Address of the installed hook.
+    address: usize,
+    /// Dropped along with the installed hook, so that a new hook reusing its
+    /// address is not mistaken for it.
+    enabled: Weak<AtomicBool>,
+}
+
+fn hook_address(hook: &PanicHook) -> usize {
+    &**hook as *const _ as *const () as usize
+}
+
+/// Installs a panic hook writing a crash report when the engine starts, the
+/// previous hook is restored when the handler is dropped.
+///
+/// Only panics of the engine thread are reported, other threads keep the
+/// previous hook. Add it before every other handler, so the event being
+/// dispatched when a handler panics is already part of the report. After the
+/// report is written an [`EngineEvent::Shutdown`] is emitted so the engine
+/// stops cleanly.
+pub struct CrashReporterEventHandler {
+    config: CrashReportConfig,
+    context: Arc<Mutex<CrashContext>>,
+    installed: Option<InstalledHook>,
+}
+
+impl CrashReporterEventHandler {
+    pub fn new(config: CrashReportConfig) -> Self {
+        Self {
+            config,
+            context: Arc::new(Mutex::new(CrashContext {
+                state: EngineState::default(),
+                events: VecDeque::new(),
+                emitter: None,
+            })),
+            installed: None,
+        }
+    }
+
+    fn install(&mut self) {
+        if self.installed.is_some() {
+            return;
+        }
+
+        let config = self.config.clone();
+        let context = Arc::clone(&self.context);
+        let engine_thread = thread::current().id();
+        let enabled = Arc::new(AtomicBool::new(true));
+        let previous = Arc::new(panic::take_hook());
+        let hook: PanicHook = {
+            let enabled = Arc::clone(&enabled);
+            let previous = Arc::clone(&previous);
+            Box::new(move |info| {
+                if enabled.load(Ordering::Relaxed) && is_engine_thread(engine_thread) {
+                    write_report(&config, &context, info);
+                }
+                previous(info);
+            })
+        };
+        self.installed = Some(InstalledHook {
+            previous,
+            address: hook_address(&hook),
+            enabled: Arc::downgrade(&enabled),
+        });
+        panic::set_hook(hook);
+    }
+
+    fn record(&self, event: &EventBox) {
+        let Ok(mut context) = self.context.lock() else {
+            return;
+        };
+
+        if let Some(state) = event
+            .downcast_ref::<EngineEvent>()
+            .and_then(EngineState::from_event)
+        {
+            context.state = state;
+        }
+
+        let name = event.name();
+        match context.events.back_mut() {
+            Some(last) if last.name == name => last.repeated += 1,
+            _ => {
+                if context.events.len() == self.config.events {
+                    context.events.pop_front();
+                }
+                context.events.push_back(EventEntry { name, repeated: 1 });
+            }
+        }
+    }
+}
+
+impl EventHandler for CrashReporterEventHandler {
+    fn handle(&mut self, event: &EventBox) -> bool {
+        if let Some(EngineEvent::Starting) = event.downcast_ref::<EngineEvent>() {
+            self.install();
+        }
+        self.record(event);
+
+        false
+    }
+
+    fn attach(&mut self, emitter: EventEmitter) {
+        if let Ok(mut context) = self.context.lock() {
+            context.emitter = Some(emitter);
+        }
+    }
+}
+
+impl Drop for CrashReporterEventHandler {
+    fn drop(&mut self) {
+        // Hooks can not be changed while panicking
+        let Some(installed) = self.installed.take() else {
+            return;
+        };
+        if thread::panicking() {
+            return;
+        }
+
+        // A hook installed after ours keeps calling it, it only forwards from
+        // now on
+        let Some(enabled) = installed.enabled.upgrade() else {
+            return;
+        };
+        enabled.store(false, Ordering::Relaxed);
+        let current = panic::take_hook();
+        if hook_address(&current) != installed.address {
+            panic::set_hook(current);
+            return;
+        }
+
+        drop(current);
+        match Arc::try_unwrap(installed.previous) {
+            Ok(previous) => panic::set_hook(previous),
+            Err(previous) => panic::set_hook(Box::new(move |info| previous(info))),
+        }
+    }
+}
+
+fn is_engine_thread(engine_thread: ThreadId) -> bool {
+    thread::current().id() == engine_thread
+}
+
+/// Path of a new report, unique even for several panics in the same second.
+fn report_path(directory: &Path) -> PathBuf {
+    static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let count = REPORTS.fetch_add(1, Ordering::Relaxed);
+    directory.join(format!("crash-{unix_time}-{}-{count}.txt", process::id()))
+}
+
+fn write_report(config: &CrashReportConfig, context: &Mutex<CrashContext>, info: &PanicHookInfo) {
+    // The panic may have happened while the context was locked
+    let context = context.try_lock().ok();
+
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "<non-string panic payload>".to_string());
+    let location = info
+        .location()
+        .map(|location| location.to_string())
+        .unwrap_or_else(|| "<unknown>".to_string());
+    let report = render_report(
+        config,
+        context.as_deref(),
+        &message,
+        &location,
+        &Backtrace::force_capture(),
+    );
+
+    let path = report_path(&config.directory);
+    match fs::create_dir_all(&config.directory).and_then(|_| fs::write(&path, report)) {
+        Ok(()) => eprintln!("Crash report written to {}", path.display()),
+        Err(err) => eprintln!("Failed to write crash report: {err}"),
+    }
+
+    if let Some(emitter) = context.and_then(|context| context.emitter.clone()) {
+        emitter.emit(EngineEvent::Shutdown);
+    }
+}
+
+fn render_report(
+    config: &CrashReportConfig,
+    context: Option<&CrashContext>,
+    message: &str,
+    location: &str,
+    backtrace: &Backtrace,
+) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "UnnamedEngine crash report");
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Message: {message}");
+    let _ = writeln!(report, "Location: {location}");
+    let _ = writeln!(
+        report,
+        "Thread: {}",
+        thread::current().name().unwrap_or("<unnamed>")
+    );
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Build:");
+    let _ = writeln!(report, "  engine version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        report,
+        "  profile: {}",
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        }
+    );
+    let _ = writeln!(
+        report,
+        "  target: {}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    let _ = writeln!(report);
+
+    match context {
+        Some(context) => {
+            let _ = writeln!(report, "Engine state: {:?}", context.state);
+            let _ = writeln!(report);
+            let _ = writeln!(report, "Last events (oldest first):");
+            for entry in &context.events {
+                match entry.repeated {
+                    1 => {
+                        let _ = writeln!(report, "  {}", entry.name);
+                    }
+                    repeated => {
+                        let _ = writeln!(report, "  {} (x{repeated})", entry.name);
+                    }
+                }
+            }
+        }
+        None => {
+            let _ = writeln!(report, "Engine state: <unavailable>");
+        }
+    }
+    let _ = writeln!(report);
+
+    if let Some(buffer) = &config.log_buffer {
+        let _ = writeln!(report, "Recent log lines:");
+        for record in buffer.recent(config.log_lines) {
+            let _ = writeln!(report, "  {record}");
+        }
+        let _ = writeln!(report);
+    }
+
+    let _ = writeln!(report, "Backtrace:");
+    let _ = writeln!(report, "{backtrace}");
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tracing::Level;
+
+    use super::*;
+    use crate::buffer::LogRecord;
+
+    #[test]
+    fn report_contents() {
+        let buffer = LogBuffer::new(8);
+        buffer.push(LogRecord {
+            timestamp: SystemTime::now(),
+            level: Level::WARN,
+            target: "game".to_string(),
+            message: "about to explode".to_string(),
+        });
+        let handler = CrashReporterEventHandler::new(
+            CrashReportConfig::new(std::env::temp_dir()).with_log_buffer(buffer, 8),
+        );
+        for event in [
+            EngineEvent::Starting,
+            EngineEvent::Update,
+            EngineEvent::Update,
+        ] {
+            handler.record(&EventBox::new(event));
+        }
+
+        let context = handler.context.lock().unwrap();
+        let report = render_report(
+            &handler.config,
+            Some(&context),
+            "boom",
+            "src/main.rs:1:1",
+            &Backtrace::disabled(),
+        );
+
+        assert!(
+            report.contains("Message: boom"),
+            "should contain the message"
+        );
+        assert!(
+            report.contains("Engine state: Started"),
+            "should contain the engine state"
+        );
+        assert!(
+            report.contains("EngineEvent (x3)"),
+            "should collapse repeated events"
+        );
+        assert!(
+            report.contains("WARN game: about to explode"),
+            "should contain the recent log lines"
+        );
+    }
+
+    #[test]
+    fn report_paths() {
+        let directory = std::env::temp_dir();
+        assert_ne!(
+            report_path(&directory),
+            report_path(&directory),
+            "should not overwrite reports written in the same second"
+        );
+    }
+
+    #[test]
+    fn restore_hooks() {
+        static EARLIER: AtomicUsize = AtomicUsize::new(0);
+        static LATER: AtomicUsize = AtomicUsize::new(0);
+
+        panic::set_hook(Box::new(|_| {
+            EARLIER.fetch_add(1, Ordering::Relaxed);
+        }));
+        // Installed from another thread, so that panics here are not reported
+        let install = || {
+            thread::spawn(|| {
+                let mut handler =
+                    CrashReporterEventHandler::new(CrashReportConfig::new(std::env::temp_dir()));
+                handler.handle(&EventBox::new(EngineEvent::Starting));
+                handler
+            })
+            .join()
+            .unwrap()
+        };
+
+        drop(install());
+        let _ = panic::catch_unwind(|| panic!("restored"));
+        assert_eq!(
+            EARLIER.load(Ordering::Relaxed),
+            1,
+            "should restore the previous hook"
+        );
+
+        let handler = install();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            LATER.fetch_add(1, Ordering::Relaxed);
+            previous(info);
+        }));
+        drop(handler);
+        let _ = panic::catch_unwind(|| panic!("kept"));
+        assert_eq!(
+            (
+                EARLIER.load(Ordering::Relaxed),
+                LATER.load(Ordering::Relaxed)
+            ),
+            (2, 1),
+            "should keep hooks installed after its own"
+        );
+
+        let _ = panic::take_hook();
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// When the active log file is rotated into an archive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Keep appending to the same file forever.
+    #[default]
+    Never,
+    /// Rotate once the active file would grow past the given amount of bytes.
+    Size(u64),
+    /// Rotate when the UTC hour changes.
+    Hourly,
+    /// Rotate when the UTC day changes.
+    Daily,
+}
+
+impl Rotation {
+    /// Returns the time period the given instant falls into, used to detect
+    /// hour or day changes.
+    fn period(&self, time: SystemTime) -> u64 {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        match self {
+            Rotation::Never | Rotation::Size(_) => 0,
+            Rotation::Hourly => secs / 3600,
+            Rotation::Daily => secs / 86400,
+        }
+    }
+}
+
+/// Configuration of the log file output.
+///
+/// The active file is `<directory>/<file_name>.log`. On rotation it becomes
+/// `<file_name>.1.log`, older archives are shifted up by one and anything past
+/// the retention count is deleted.
+#[derive(Debug, Clone)]
+pub struct FileLogConfig {
+    directory: PathBuf,
+    file_name: String,
+    rotation: Rotation,
+    retention: usize,
+}
+
+impl FileLogConfig {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            file_name: "unen".to_string(),
+            rotation: Rotation::default(),
+            retention: 5,
+        }
+    }
+
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = file_name.into();
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Amount of rotated archives kept besides the active file.
+    pub fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Path of the file currently being written to.
+    pub fn active_path(&self) -> PathBuf {
+        self.directory.join(format!("{}.log", self.file_name))
+    }
+
+    /// Path of the archive with the given index, `1` being the newest.
+    pub fn archive_path(&self, index: usize) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}.log", self.file_name, index))
+    }
+}
+
+/// Blocking writer that appends to the active log file and rotates it
+/// according to a [`FileLogConfig`].
+///
+/// Normally wrapped in a non-blocking writer so the actual I/O happens on a
+/// background thread.
+pub struct RotatingFileWriter {
+    config: FileLogConfig,
+    file: File,
+    written: u64,
+    period: u64,
+}
+
+impl RotatingFileWriter {
+    pub fn new(config: FileLogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+
+        let now = SystemTime::now();
+        let active = config.active_path();
+
+        // A file left by a previous run in an older period is archived first
+        if let Ok(metadata) = fs::metadata(&active) {
+            let modified = metadata.modified().unwrap_or(now);
+            if config.rotation.period(modified) != config.rotation.period(now) {
+                Self::shift_archives(&config)?;
+            }
+        }
+
+        let file = open_append(&active)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            period: config.rotation.period(now),
+            config,
+            file,
+            written,
+        })
+    }
+
+    fn should_rotate(&self, incoming: usize, now: SystemTime) -> bool {
+        match self.config.rotation {
+            Rotation::Never => false,
+            Rotation::Size(max) => self.written > 0 && self.written + incoming as u64 > max,
+            Rotation::Hourly | Rotation::Daily => self.config.rotation.period(now) != self.period,
+        }
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        self.file.flush()?;
+        Self::shift_archives(&self.config)?;
+
+        self.file = open_append(&self.config.active_path())?;
+        self.written = 0;
+        self.period = self.config.rotation.period(now);
+        Ok(())
+    }
+
+    /// Moves the active file into the first archive slot, shifting older
+    /// archives and deleting the ones past the retention count.
+    fn shift_archives(config: &FileLogConfig) -> io::Result<()> {
+        if config.retention == 0 {
+            return remove_if_exists(&config.active_path());
+        }
+
+        remove_if_exists(&config.archive_path(config.retention))?;
+        for index in (1..config.retention).rev() {
+            let from = config.archive_path(index);
+            if from.exists() {
+                fs::rename(from, config.archive_path(index + 1))?;
+            }
+        }
+
+        let active = config.active_path();
+        if active.exists() {
+            fs::rename(active, config.archive_path(1))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = SystemTime::now();
+        if self.should_rotate(buf.len(), now) {
+            self.rotate(now)?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(name: &str) -> FileLogConfig {
+        let directory =
+            std::env::temp_dir().join(format!("unen_logging_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        FileLogConfig::new(directory)
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let config = temp_config("size")
+            .with_rotation(Rotation::Size(16))
+            .with_retention(2);
+        let mut writer = RotatingFileWriter::new(config.clone()).expect("should open log file");
+
+        for line in [
+            "first line 00\n",
+            "second line 0\n",
+            "third line 00\n",
+            "fourth line 0\n",
+        ] {
+            writer.write_all(line.as_bytes()).expect("should write");
+        }
+        writer.flush().expect("should flush");
+
+        let read = |path: PathBuf| fs::read_to_string(path).expect("file should exist");
+        assert_eq!(
+            read(config.active_path()),
+            "fourth line 0\n",
+            "active file should hold the newest line"
+        );
+        assert_eq!(
+            read(config.archive_path(1)),
+            "third line 00\n",
+            "first archive should hold the previous line"
+        );
+        assert_eq!(
+            read(config.archive_path(2)),
+            "second line 0\n",
+            "second archive should hold the oldest kept line"
+        );
+        assert!(
+            !config.archive_path(3).exists(),
+            "should not keep archives past the retention count"
+        );
+
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+
+    #[test]
+    fn appends_without_rotation() {
+        let config = temp_config("never");
+        for _ in 0..2 {
+            let mut writer = RotatingFileWriter::new(config.clone()).expect("should open log file");
+            writer.write_all(b"line\n").expect("should write");
+        }
+
+        let content = fs::read_to_string(config.active_path()).expect("file should exist");
+        assert_eq!(content, "line\nline\n", "should append across runs");
+        assert!(!config.archive_path(1).exists(), "should not rotate");
+
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+}
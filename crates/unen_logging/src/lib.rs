@@ -1,5 +1,17 @@
+mod buffer;
+mod config;
+mod crash;
+mod file;
 mod logger;
+mod profiler;
 
 pub mod prelude {
-    pub use crate::logger::LoggerEventHandler;
+    pub use crate::{
+        buffer::LogBuffer, buffer::LogBufferLayer, buffer::LogEvent, buffer::LogRecord,
+        config::Error as LoggingError, config::LogFormat, config::LoggingConfig,
+        config::LoggingGuard, config::LOG_ENV_VAR, crash::CrashReportConfig,
+        crash::CrashReporterEventHandler, file::FileLogConfig, file::RotatingFileWriter,
+        file::Rotation, logger::LoggerEventHandler, profiler::Profiler, profiler::ProfilerConfig,
+        profiler::ProfilerEvent, profiler::ProfilerLayer, profiler::FRAME_SPAN,
+    };
 }
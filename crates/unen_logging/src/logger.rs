@@ -1,40 +1,108 @@
-use unen_event::prelude::{EngineEvent, EventBox, EventHandler};
+use unen_event::prelude::{EngineEvent, EventBox, EventEmitter, EventHandler};
+
+use crate::{
+    buffer::LogEvent,
+    config::{Error, LoggingConfig, LoggingGuard},
+    profiler::ProfilerEvent,
+};
 
 /// Event handler
-pub struct LoggerEventHandler;
+pub struct LoggerEventHandler {
+    config: LoggingConfig,
+    guard: Option<LoggingGuard>,
+    emitter: Option<EventEmitter>,
+    /// Position in the log buffer up to which records were republished.
+    cursor: u64,
+}
+
+impl LoggerEventHandler {
+    pub fn new(config: LoggingConfig) -> Self {
+        Self {
+            config,
+            guard: None,
+            emitter: None,
+            cursor: 0,
+        }
+    }
+
+    /// Emits a [`LogEvent`] for every record captured since the last call.
+    fn publish_records(&mut self) {
+        let (Some(buffer), Some(emitter)) = (self.config.buffer(), &self.emitter) else {
+            return;
+        };
+
+        let (records, cursor) = buffer.since(self.cursor);
+        self.cursor = cursor;
+        for record in records {
+            emitter.emit(LogEvent { record });
+        }
+    }
+
+    fn init(&mut self) {
+        match self.config.init() {
+            Ok(guard) => {
+                self.guard = Some(guard);
+                if let (Some(config), Some(profiler)) =
+                    (self.config.profiler_config(), self.config.profiler())
+                {
+                    if config.capture_on_start() {
+                        profiler.capture(config.frames());
+                    }
+                }
+            }
+            Err(Error::AlreadyInitialized(_)) => {
+                log::debug!("A global subscriber is already installed, keeping it");
+            }
+            Err(err) => eprintln!("Failed to initialize logging: {err}"),
+        }
+    }
+}
 
 impl EventHandler for LoggerEventHandler {
     fn handle(&mut self, event: &EventBox) -> bool {
         if let Some(engine_event) = event.downcast_ref::<EngineEvent>() {
             match engine_event {
                 EngineEvent::Starting => {
-                    tracing_subscriber::fmt()
-                        .with_max_level(tracing::Level::INFO)
-                        .with_target(false)
-                        .with_level(true)
-                        .init();
+                    self.init();
                     log::info!("UnnamedEngine is starting")
                 }
                 EngineEvent::Started => {
                     log::info!("UnnamedEngine successfully started");
                 }
-                EngineEvent::Update => {}
+                EngineEvent::FixedUpdate { .. } => {}
+                EngineEvent::Update => self.publish_records(),
+                EngineEvent::Shutdown => {
+                    log::info!("UnnamedEngine shutdown requested");
+                }
                 EngineEvent::Stopping => {
                     log::info!("UnnamedEngine is stopping");
                 }
                 EngineEvent::Stopped => {
                     log::info!("UnnamedEngine successfully stopped");
-                    log::info!("See you again :D")
+                    log::info!("See you again :D");
+                    // Flushes the background writers
+                    self.guard = None;
                 }
             }
         }
 
+        if let Some(ProfilerEvent::Capture { frames }) = event.downcast_ref::<ProfilerEvent>() {
+            match self.config.profiler() {
+                Some(profiler) => profiler.capture(*frames),
+                None => log::warn!("Profiler capture requested but the profiler is disabled"),
+            }
+        }
+
         false
     }
+
+    fn attach(&mut self, emitter: EventEmitter) {
+        self.emitter = Some(emitter);
+    }
 }
 
 impl Default for LoggerEventHandler {
     fn default() -> Self {
-        Self
+        Self::new(LoggingConfig::default())
     }
 }
@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use unen_event::prelude::Event;
+
+/// Name of the span the runners open around every frame, used to count
+/// captured frames.
+pub const FRAME_SPAN: &str = "frame";
+
+/// Asks the profiler to capture the given amount of frames, starting with the
+/// next one.
+#[derive(Debug, Clone, Event)]
+pub enum ProfilerEvent {
+    Capture { frames: usize },
+}
+
+/// Configuration of the frame profiler.
+#[derive(Debug, Clone)]
+pub struct ProfilerConfig {
+    directory: PathBuf,
+    frames: usize,
+    capture_on_start: bool,
+}
+
+impl ProfilerConfig {
+    /// Traces are written as `trace-<unix time>.json` into `directory`.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            frames: 60,
+            capture_on_start: false,
+        }
+    }
+
+    /// Amount of frames captured when the capture is started from the
+    /// configuration.
+    pub fn with_frames(mut self, frames: usize) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    /// Whether the first frames after the engine started are captured.
+    pub fn with_capture_on_start(mut self, capture_on_start: bool) -> Self {
+        self.capture_on_start = capture_on_start;
+        self
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn capture_on_start(&self) -> bool {
+        self.capture_on_start
+    }
+}
+
+/// A single Chrome Trace Event.
+struct TraceEvent {
+    name: String,
+    phase: char,
+    timestamp: f64,
+    thread: u64,
+    args: Vec<(&'static str, String)>,
+}
+
+#[derive(Default)]
+struct Capture {
+    /// Frames still to record, the capture is active while non-zero.
+    remaining: usize,
+    /// Frames requested but not started yet, waiting for a frame boundary.
+    requested: usize,
+    events: Vec<TraceEvent>,
+    threads: HashMap<u64, String>,
+}
+
+struct ProfilerInner {
+    directory: PathBuf,
+    epoch: Instant,
+    /// Fast path so spans cost a single atomic load while idle.
+    active: AtomicBool,
+    capture: Mutex<Capture>,
+}
+
+/// Handle to the frame profiler.
+///
+/// Records span enters and exits while a capture is running and exports them
+/// as a Chrome Trace Event file, loadable in `chrome://tracing` or Perfetto.
+#[derive(Clone)]
+pub struct Profiler {
+    inner: Arc<ProfilerInner>,
+}
+
+impl Profiler {
+    pub fn new(config: &ProfilerConfig) -> Self {
+        Self {
+            inner: Arc::new(ProfilerInner {
+                directory: config.directory.clone(),
+                epoch: Instant::now(),
+                active: AtomicBool::new(false),
+                capture: Mutex::new(Capture::default()),
+            }),
+        }
+    }
+
+    /// Captures the next `frames` frames. Ignored while a capture is running.
+    pub fn capture(&self, frames: usize) {
+        let mut capture = self.lock();
+        if capture.remaining > 0 || frames == 0 {
+            return;
+        }
+        capture.requested = frames;
+        self.inner.active.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.inner.active.load(Ordering::Relaxed)
+    }
+
+    pub fn layer(&self) -> ProfilerLayer {
+        ProfilerLayer {
+            profiler: self.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Capture> {
+        self.inner
+            .capture
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn timestamp(&self) -> f64 {
+        self.inner.epoch.elapsed().as_secs_f64() * 1_000_000.0
+    }
+
+    fn record(&self, name: &str, phase: char, args: Vec<(&'static str, String)>) {
+        let timestamp = self.timestamp();
+        let mut capture = self.lock();
+
+        let is_frame = name == FRAME_SPAN;
+        if capture.remaining == 0 {
+            // Captures always start at the beginning of a frame
+            if !(is_frame && phase == 'B' && capture.requested > 0) {
+                return;
+            }
+            capture.remaining = std::mem::take(&mut capture.requested);
+        }
+
+        let thread = current_thread_id();
+        capture.threads.entry(thread).or_insert_with(|| {
+            thread::current()
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("thread {thread}"))
+        });
+
+        let label = args.iter().fold(name.to_string(), |mut label, (_, value)| {
+            let _ = write!(label, " {value}");
+            label
+        });
+        capture.events.push(TraceEvent {
+            name: label,
+            phase,
+            timestamp,
+            thread,
+            args,
+        });
+
+        if is_frame && phase == 'E' {
+            capture.remaining -= 1;
+            if capture.remaining == 0 {
+                let finished = std::mem::take(&mut *capture);
+                self.inner.active.store(false, Ordering::Relaxed);
+                self.export(finished);
+            }
+        }
+    }
+
+    /// Writes the finished capture on a background thread so the frame that
+    /// completed it is not stalled by the file I/O.
+    fn export(&self, capture: Capture) {
+        let directory = self.inner.directory.clone();
+        let path = trace_path(&directory);
+
+        thread::spawn(move || {
+            let result = fs::create_dir_all(&directory)
+                .and_then(|_| fs::write(&path, to_chrome_trace(&capture).to_string()));
+            match result {
+                Ok(()) => log::info!("Profiler trace written to {}", path.display()),
+                Err(err) => log::error!("Failed to write profiler trace: {err}"),
+            }
+        });
+    }
+}
+
+impl fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Profiler")
+            .field("directory", &self.inner.directory)
+            .field("capturing", &self.is_capturing())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Path of a new trace, unique even for several captures in the same second.
+fn trace_path(directory: &Path) -> PathBuf {
+    static TRACES: AtomicUsize = AtomicUsize::new(0);
+
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let count = TRACES.fetch_add(1, Ordering::Relaxed);
+    directory.join(format!("trace-{unix_time}-{}-{count}.json", process::id()))
+}
+
+fn to_chrome_trace(capture: &Capture) -> Value {
+    let metadata = capture.threads.iter().map(|(thread, name)| {
+        json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": thread,
+            "args": { "name": name },
+        })
+    });
+
+    let events = capture.events.iter().map(|event| {
+        let args: serde_json::Map<String, Value> = event
+            .args
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::from(value.as_str())))
+            .collect();
+        json!({
+            "name": event.name,
+            "cat": "unen",
+            "ph": event.phase.to_string(),
+            "ts": event.timestamp,
+            "pid": 1,
+            "tid": event.thread,
+            "args": args,
+        })
+    });
+
+    json!({
+        "traceEvents": metadata.chain(events).collect::<Vec<_>>(),
+        "displayTimeUnit": "ms",
+    })
+}
+
+fn current_thread_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+/// Span fields stored in the span extensions until the span is entered.
+struct SpanArgs(Vec<(&'static str, String)>);
+
+/// Subscriber layer feeding span enters and exits into a [`Profiler`].
+pub struct ProfilerLayer {
+    profiler: Profiler,
+}
+
+impl<S> Layer<S> for ProfilerLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = ArgsVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanArgs(visitor.0));
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if !self.profiler.is_capturing() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            let args = span
+                .extensions()
+                .get::<SpanArgs>()
+                .map(|args| args.0.clone())
+                .unwrap_or_default();
+            self.profiler.record(span.name(), 'B', args);
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if !self.profiler.is_capturing() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            self.profiler.record(span.name(), 'E', Vec::new());
+        }
+    }
+}
+
+#[derive(Default)]
+struct ArgsVisitor(Vec<(&'static str, String)>);
+
+impl Visit for ArgsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{value:?}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_requested_frames() {
+        let profiler = Profiler::new(&ProfilerConfig::new(std::env::temp_dir()));
+
+        // Spans before the request are ignored
+        profiler.record(FRAME_SPAN, 'B', Vec::new());
+        profiler.record(FRAME_SPAN, 'E', Vec::new());
+        assert!(profiler.lock().events.is_empty(), "should not be capturing");
+
+        profiler.capture(2);
+        // Spans outside of a frame do not start the capture
+        profiler.record("step", 'B', Vec::new());
+        profiler.record("step", 'E', Vec::new());
+        assert!(profiler.lock().events.is_empty(), "should wait for a frame");
+
+        profiler.record(FRAME_SPAN, 'B', Vec::new());
+        profiler.record("handle", 'B', vec![("handler", "Foo".to_string())]);
+        profiler.record("handle", 'E', Vec::new());
+        profiler.record(FRAME_SPAN, 'E', Vec::new());
+        {
+            let capture = profiler.lock();
+            assert_eq!(capture.events.len(), 4, "should record the first frame");
+            assert_eq!(capture.events[1].name, "handle Foo", "should label spans");
+            assert_eq!(capture.remaining, 1, "should have one frame left");
+        }
+
+        profiler.record(FRAME_SPAN, 'B', Vec::new());
+        profiler.record(FRAME_SPAN, 'E', Vec::new());
+        assert!(!profiler.is_capturing(), "should stop after two frames");
+        assert!(
+            profiler.lock().events.is_empty(),
+            "should hand the events to the exporter"
+        );
+    }
+
+    #[test]
+    fn chrome_trace_format() {
+        let mut capture = Capture::default();
+        capture.threads.insert(1, "main".to_string());
+        capture.events.push(TraceEvent {
+            name: "frame".to_string(),
+            phase: 'B',
+            timestamp: 1.5,
+            thread: 1,
+            args: Vec::new(),
+        });
+
+        let trace = to_chrome_trace(&capture);
+        let events = trace["traceEvents"]
+            .as_array()
+            .expect("should have an event list");
+        assert_eq!(events.len(), 2, "should contain metadata and the event");
+        assert_eq!(events[0]["ph"], "M", "should name the threads first");
+        assert_eq!(events[1]["ph"], "B", "should keep the phase");
+        assert_eq!(events[1]["ts"], 1.5, "should keep the timestamp");
+    }
+
+    #[test]
+    fn trace_paths() {
+        let directory = std::env::temp_dir();
+        assert_ne!(
+            trace_path(&directory),
+            trace_path(&directory),
+            "should not overwrite traces captured in the same second"
+        );
+    }
+}
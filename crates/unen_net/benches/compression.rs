@@ -0,0 +1,57 @@
+use std::hint::black_box;
+
+use bincode::Encode;
+use criterion::{criterion_group, criterion_main, Criterion};
+use unen_net::protocol::packet::{Compression, Packet, PacketHeader, PacketKind};
+
+/// Replicated state of an entity, shaped like what a game sends every tick.
+#[derive(Encode)]
+struct EntityState {
+    id: u32,
+    position: [f32; 3],
+    velocity: [f32; 3],
+    rotation: [f32; 4],
+    health: u16,
+    flags: u8,
+}
+
+fn snapshot(count: u32) -> Vec<EntityState> {
+    (0..count)
+        .map(|id| EntityState {
+            id,
+            position: [id as f32 * 2.0, 0.0, (id % 8) as f32],
+            velocity: [if id % 3 == 0 { 1.5 } else { 0.0 }, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            health: 100,
+            flags: 0,
+        })
+        .collect()
+}
+
+fn compression(c: &mut Criterion) {
+    let snapshot = snapshot(20);
+    let header = PacketHeader::new(PacketKind::Data);
+
+    let raw = Packet::from_data_with(header, &snapshot, Compression::None).unwrap();
+    let lz4 = Packet::from_data_with(header, &snapshot, Compression::Lz4).unwrap();
+    let (raw_len, lz4_len) = (raw.as_bytes().len(), lz4.as_bytes().len());
+    println!(
+        "snapshot of 20 entities: {raw_len} bytes raw, {lz4_len} bytes with lz4 ({:.0}% saved)",
+        (1.0 - lz4_len as f32 / raw_len as f32) * 100.0
+    );
+
+    let mut group = c.benchmark_group("snapshot");
+    group.bench_function("encode", |b| {
+        b.iter(|| Packet::from_data_with(header, black_box(&snapshot), Compression::None))
+    });
+    group.bench_function("encode_lz4", |b| {
+        b.iter(|| Packet::from_data_with(header, black_box(&snapshot), Compression::Lz4))
+    });
+    group.bench_function("decode_lz4", |b| {
+        b.iter(|| black_box(&lz4).to_data::<Vec<(u32, [f32; 3], [f32; 3], [f32; 4], u16, u8)>>())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compression);
+criterion_main!(benches);
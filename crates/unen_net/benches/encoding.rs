@@ -0,0 +1,129 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use unen_net::protocol::{
+    buffer::BufferPool,
+    packet::{self, Compression, Packet, PacketHeader, PacketKind, HEADER_SIZE},
+};
+
+/// Replicated state of an entity, shaped like what a game sends every tick.
+type EntityState = (u32, [f32; 3], [f32; 3], [f32; 4], u16, u8);
+
+fn snapshot(count: u32) -> Vec<EntityState> {
+    (0..count)
+        .map(|id| {
+            (
+                id,
+                [id as f32 * 2.0, 0.0, 0.0],
+                [0.0; 3],
+                [0.0, 0.0, 0.0, 1.0],
+                100,
+                0,
+            )
+        })
+        .collect()
+}
+
+/// Encoding and receiving as they were before packets were encoded into
+/// pooled buffers and decoded from the receive buffer, the baseline of the
+/// benchmarks.
+mod baseline {
+    use bincode::{Decode, Encode};
+    use crc32fast::Hasher;
+    use unen_net::protocol::packet::{
+        self, Error, END_CHECK, HEADER_SIZE, PACKET_MAX_SIZE, PROTOCOL_ID,
+    };
+
+    const FRAMING_SIZE: usize = 4 + HEADER_SIZE + 4;
+
+    /// Encodes the payload into its own buffer, then copies it into the
+    /// packet.
+    pub fn encode<T: Encode>(header: &[u8; HEADER_SIZE], value: T) -> Result<Vec<u8>, Error> {
+        let mut payload = Vec::new();
+        bincode::encode_into_std_write(value, &mut payload, bincode::config::standard())?;
+        if payload.len() + FRAMING_SIZE > PACKET_MAX_SIZE {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let mut buf = vec![0u8; 4];
+        buf.extend_from_slice(header);
+        buf.extend(&payload);
+        buf.extend_from_slice(&END_CHECK.to_le_bytes());
+
+        let crc = checksum(&buf[4..]);
+        buf[..4].copy_from_slice(&crc.to_le_bytes());
+        Ok(buf)
+    }
+
+    /// Copies every received datagram out of the receive buffer, then
+    /// decodes the owned copy.
+    pub fn receive<T: Decode<()>>(datagram: &[u8]) -> Vec<Result<T, Error>> {
+        let received = vec![datagram.to_vec()];
+        received.into_iter().map(|data| decode(&data)).collect()
+    }
+
+    fn decode<T: Decode<()>>(data: &[u8]) -> Result<T, Error> {
+        if data.len() < FRAMING_SIZE {
+            return Err(Error::InvalidEnd);
+        }
+
+        let end_bytes = &data[data.len() - 4..];
+        if u32::from_le_bytes(end_bytes.try_into().unwrap()) != END_CHECK {
+            return Err(Error::InvalidEnd);
+        }
+        let crc_orig = u32::from_le_bytes(data[..4].try_into().unwrap());
+        if checksum(&data[4..]) != crc_orig {
+            return Err(Error::CrcMismatch);
+        }
+
+        packet::decode_header(data)?;
+        let payload = &data[4 + HEADER_SIZE..data.len() - 4];
+        let value =
+            bincode::decode_from_slice(payload, bincode::config::standard()).map(|(v, _)| v)?;
+        Ok(value)
+    }
+
+    fn checksum(data: &[u8]) -> u32 {
+        let mut hasher = Hasher::new();
+        hasher.update(PROTOCOL_ID);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+fn encoding(c: &mut Criterion) {
+    let snapshot = snapshot(20);
+    let header = PacketHeader::new(PacketKind::Data);
+    let datagram = Packet::from_data_with(header, &snapshot, Compression::None)
+        .unwrap()
+        .as_bytes()
+        .to_vec();
+    // The header is encoded the same way before and after
+    let header_bytes: [u8; HEADER_SIZE] = datagram[4..4 + HEADER_SIZE].try_into().unwrap();
+
+    let mut group = c.benchmark_group("encode");
+    group.bench_function("baseline", |b| {
+        b.iter(|| baseline::encode(&header_bytes, black_box(&snapshot)))
+    });
+    group.bench_function("pooled", |b| {
+        let mut pool = BufferPool::default();
+        b.iter(|| {
+            let mut buf = pool.take();
+            packet::encode_into(header, black_box(&snapshot), Compression::None, &mut buf).unwrap();
+            pool.give(buf);
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("baseline", |b| {
+        b.iter(|| baseline::receive::<Vec<EntityState>>(black_box(&datagram)))
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| packet::decode_from::<Vec<EntityState>>(black_box(&datagram)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encoding);
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+//! Headless dedicated server, ticking at a fixed rate without any window or
+//! renderer.
+//!
+//! ```sh
+//! cargo run -p unen_net --example unen_server -- server.json
+//! ```
+//!
+//! The optional config file may set `bind_address`, `max_players` and
+//! `tick_rate`, missing fields keep their default. Ctrl-C or SIGTERM stops
+//! the server, disconnecting every client.
+
+use std::{
+    fs,
+    net::SocketAddr,
+    process,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use unen_core::prelude::create_engine;
+use unen_event::prelude::{EngineEvent, EventBox, EventEmitter, EventHandler};
+use unen_logging::prelude::LoggerEventHandler;
+use unen_net::server::{create_server, NetworkServerEvent, NetworkServerHandler};
+use unen_runner::prelude::MininalRunner;
+
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:7777";
+const DEFAULT_MAX_PLAYERS: usize = 16;
+const DEFAULT_TICK_RATE: u32 = 30;
+
+/// Settings of the dedicated server.
+#[derive(Debug, Clone)]
+struct ServerConfig {
+    bind_address: SocketAddr,
+    max_players: usize,
+    tick_rate: u32,
+}
+
+impl ServerConfig {
+    /// Reads the config file at `path`, or the defaults without one.
+    fn load(path: Option<&str>) -> Result<Self, String> {
+        let mut config = Self::default();
+        let Some(path) = path else {
+            return Ok(config);
+        };
+
+        let content = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let value: Value =
+            serde_json::from_str(&content).map_err(|err| format!("{path}: {err}"))?;
+
+        if let Some(bind_address) = value.get("bind_address") {
+            config.bind_address = bind_address
+                .as_str()
+                .and_then(|addr| addr.parse().ok())
+                .ok_or(format!("{path}: invalid bind_address {bind_address}"))?;
+        }
+        if let Some(max_players) = value.get("max_players") {
+            config.max_players = max_players
+                .as_u64()
+                .and_then(|max| usize::try_from(max).ok())
+                .ok_or(format!("{path}: invalid max_players {max_players}"))?;
+        }
+        if let Some(tick_rate) = value.get("tick_rate") {
+            config.tick_rate = tick_rate
+                .as_u64()
+                .and_then(|rate| u32::try_from(rate).ok())
+                .filter(|rate| *rate > 0)
+                .ok_or(format!("{path}: invalid tick_rate {tick_rate}"))?;
+        }
+        Ok(config)
+    }
+
+    fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.tick_rate
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: DEFAULT_BIND_ADDRESS
+                .parse()
+                .expect("should be a valid address"),
+            max_players: DEFAULT_MAX_PLAYERS,
+            tick_rate: DEFAULT_TICK_RATE,
+        }
+    }
+}
+
+/// Logs how long the work of each fixed tick takes, and a summary every
+/// second of ticks.
+struct TickTimer {
+    interval: Duration,
+    /// Ticks per summary.
+    window: u32,
+    total: Duration,
+    worst: Duration,
+    late: u32,
+}
+
+impl TickTimer {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            interval: config.tick_interval(),
+            window: config.tick_rate,
+            total: Duration::ZERO,
+            worst: Duration::ZERO,
+            late: 0,
+        }
+    }
+
+    fn tick(&mut self, tick: u32, elapsed: Duration) {
+        tracing::trace!("tick {tick} took {elapsed:?}");
+        self.total += elapsed;
+        self.worst = self.worst.max(elapsed);
+        if elapsed > self.interval {
+            self.late += 1;
+        }
+
+        if (tick + 1).is_multiple_of(self.window) {
+            tracing::info!(
+                "tick {tick}: average {:?}, worst {:?}, {} over budget in the last {} ticks",
+                self.total / self.window,
+                self.worst,
+                self.late,
+                self.window
+            );
+            self.total = Duration::ZERO;
+            self.worst = Duration::ZERO;
+            self.late = 0;
+        }
+    }
+}
+
+/// Runs the server once per fixed tick, timing it, and logs its connections.
+struct ServerHandler {
+    config: ServerConfig,
+    server: NetworkServerHandler,
+    timer: TickTimer,
+}
+
+impl ServerHandler {
+    fn new(config: ServerConfig, server: NetworkServerHandler) -> Self {
+        Self {
+            timer: TickTimer::new(&config),
+            server: server.with_fixed_update(),
+            config,
+        }
+    }
+}
+
+impl EventHandler for ServerHandler {
+    fn handle(&mut self, event: &EventBox) -> bool {
+        match event.downcast_ref::<EngineEvent>() {
+            Some(EngineEvent::Started) => {
+                tracing::info!(
+                    "listening on {} for up to {} players at {} ticks per second",
+                    self.config.bind_address,
+                    self.config.max_players,
+                    self.config.tick_rate
+                );
+            }
+            Some(EngineEvent::FixedUpdate { tick }) => {
+                let start = Instant::now();
+                self.server.handle(event);
+                self.timer.tick(*tick, start.elapsed());
+                return false;
+            }
+            Some(EngineEvent::Stopping) => {
+                tracing::info!("stopping, disconnecting every client");
+            }
+            _ => {}
+        }
+        match event.downcast_ref::<NetworkServerEvent>() {
+            Some(NetworkServerEvent::ClientConnected(id)) => {
+                tracing::info!("client {id} connected");
+            }
+            Some(NetworkServerEvent::ClientDisconnected(id, reason)) => {
+                tracing::info!("client {id} disconnected: {reason}");
+            }
+            None => {}
+        }
+        self.server.handle(event)
+    }
+
+    fn attach(&mut self, emitter: EventEmitter) {
+        self.server.attach(emitter);
+    }
+}
+
+fn main() {
+    let path = std::env::args().nth(1);
+    let config = ServerConfig::load(path.as_deref()).unwrap_or_else(|err| {
+        eprintln!("failed to read config: {err}");
+        process::exit(1);
+    });
+    let server = create_server()
+        .with_max_clients(config.max_players)
+        .listen(config.bind_address)
+        .unwrap_or_else(|err| {
+            eprintln!("failed to listen on {}: {err}", config.bind_address);
+            process::exit(1);
+        });
+
+    let _ = create_engine()
+        .set_runner(MininalRunner::default().with_fixed_timestep(config.tick_interval()))
+        .add_event_handler(LoggerEventHandler::default())
+        .add_event_handler(ServerHandler::new(
+            config,
+            NetworkServerHandler::new(server),
+        ))
+        .start()
+        .stop();
+}
@@ -0,0 +1,172 @@
+use std::{any::Any, sync::Mutex};
+
+use unen_event::prelude::{EngineEvent, Event, EventBox, EventEmitter, EventHandler};
+
+use crate::{
+    client::{ClientEvent, ConnectedClient, ConnectingClient, ConnectionAttempt, Error},
+    connection::DisconnectReason,
+    message::{AnyMessage, NetMessage},
+    protocol::channel::ChannelId,
+};
+
+/// Connection changes of a [`NetworkClientHandler`], emitted on the engine
+/// bus.
+#[derive(Debug)]
+pub enum NetworkClientEvent {
+    Connected,
+    /// The handshake failed, the client stays disconnected.
+    ConnectFailed(Error),
+    Disconnected(DisconnectReason),
+}
+
+impl Event for NetworkClientEvent {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event asking a [`NetworkClientHandler`] to send a registered message to
+/// the server, dropped while not connected.
+pub struct SendToServer {
+    channel: ChannelId,
+    message: Box<dyn AnyMessage>,
+}
+
+impl SendToServer {
+    pub fn new<M: NetMessage>(channel: impl Into<ChannelId>, message: M) -> Self {
+        Self {
+            channel: channel.into(),
+            message: Box::new(message),
+        }
+    }
+}
+
+impl Event for SendToServer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+enum ClientState {
+    Connecting(ConnectingClient),
+    Connected(ConnectedClient),
+    Disconnected,
+}
+
+/// Drives a client from the engine loop: polls and flushes it on every
+/// [`EngineEvent::Update`], emits the messages it receives as
+/// [`ServerMessage`](crate::message::ServerMessage) events and sends the
+/// [`SendToServer`] ones.
+pub struct NetworkClientHandler {
+    /// Behind a mutex only because transports need not be `Sync`, handlers
+    /// get exclusive access anyway.
+    state: Mutex<ClientState>,
+    emitter: Option<EventEmitter>,
+}
+
+impl NetworkClientHandler {
+    /// Takes over a client that started connecting.
+    pub fn new(client: ConnectingClient) -> Self {
+        Self {
+            state: Mutex::new(ClientState::Connecting(client)),
+            emitter: None,
+        }
+    }
+
+    pub fn is_connected(&mut self) -> bool {
+        matches!(self.state(), ClientState::Connected(_))
+    }
+
+    fn state(&mut self) -> &mut ClientState {
+        self.state.get_mut().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn update(state: &mut ClientState, emitter: &EventEmitter) {
+        *state = match std::mem::replace(state, ClientState::Disconnected) {
+            ClientState::Connecting(client) => match client.poll() {
+                Ok(ConnectionAttempt::Pending(client)) => ClientState::Connecting(client),
+                Ok(ConnectionAttempt::Connected(client)) => {
+                    emitter.emit(NetworkClientEvent::Connected);
+                    ClientState::Connected(client)
+                }
+                Err(err) => {
+                    emitter.emit(NetworkClientEvent::ConnectFailed(err));
+                    ClientState::Disconnected
+                }
+            },
+            ClientState::Connected(client) => Self::update_connected(client, emitter),
+            ClientState::Disconnected => ClientState::Disconnected,
+        };
+    }
+
+    fn update_connected(mut client: ConnectedClient, emitter: &EventEmitter) -> ClientState {
+        for event in client.poll() {
+            match event {
+                ClientEvent::Received(channel, data) => {
+                    if let Err(err) = client.messages().dispatch_from_server(&data, emitter) {
+                        tracing::warn!("dropped message from the server on {channel}: {err}");
+                    }
+                }
+                ClientEvent::Disconnected(reason) => {
+                    emitter.emit(NetworkClientEvent::Disconnected(reason));
+                    return ClientState::Disconnected;
+                }
+            }
+        }
+
+        // Lost datagrams are resent like any dropped one
+        let _ = client.flush();
+        ClientState::Connected(client)
+    }
+
+    fn send(&mut self, request: &SendToServer) {
+        let ClientState::Connected(client) = self.state() else {
+            return;
+        };
+
+        let result = request
+            .message
+            .encode(client.messages())
+            .map_err(Error::from)
+            .and_then(|data| client.send(request.channel, &data));
+        if let Err(err) = result {
+            tracing::warn!("failed to send message to the server: {err}");
+        }
+    }
+}
+
+impl EventHandler for NetworkClientHandler {
+    fn handle(&mut self, event: &EventBox) -> bool {
+        if let Some(request) = event.downcast_ref::<SendToServer>() {
+            self.send(request);
+            return false;
+        }
+
+        match event.downcast_ref::<EngineEvent>() {
+            Some(EngineEvent::Update) => {
+                if let Some(emitter) = self.emitter.clone() {
+                    Self::update(self.state(), &emitter);
+                }
+            }
+            Some(EngineEvent::Stopping) => {
+                if let ClientState::Connected(client) =
+                    std::mem::replace(self.state(), ClientState::Disconnected)
+                {
+                    client.disconnect();
+                    if let Some(emitter) = &self.emitter {
+                        emitter.emit(NetworkClientEvent::Disconnected(
+                            DisconnectReason::ClientLeft,
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn attach(&mut self, emitter: EventEmitter) {
+        self.emitter = Some(emitter);
+    }
+}
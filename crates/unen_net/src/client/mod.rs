@@ -1,26 +1,74 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
 
 use crate::{
-    protocol::{self},
-    socket::{self, socket_poll, socket_send},
+    conditioner::{ConditionedTransport, LinkConditionerConfig},
+    connection::{Connection, ConnectionConfig, DisconnectReason, DISCONNECT_PACKET_COUNT},
+    message::{self, MessageRegistry, NetMessage},
+    protocol::{
+        self,
+        body::PacketBody,
+        buffer::BufferPool,
+        channel::ChannelId,
+        handshake::{RejectReason, PROTOCOL_VERSION},
+        packet::Compression,
+        security::{generate_handshake_nonce, ConnectToken, HandshakeNonce, PacketCipher},
+    },
+    socket::{self, socket_recv_from, socket_send_to},
+    stats::NetworkStats,
+    transport::{Transport, UdpTransport},
 };
 
+mod handler;
+
+pub use handler::{NetworkClientEvent, NetworkClientHandler, SendToServer};
+
+/// How often handshake packets are resent while no answer arrives.
+pub const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_millis(100);
+/// Default time a connection attempt may take before it is given up, see
+/// [`ConnectionConfig::with_handshake_timeout`].
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to bind socket: {0}")]
     SocketBindFailed(String),
-    #[error("failed to set socket to nonblocking: {0}")]
-    SocketNonBlockingFailed(String),
-    #[error("failed to lock client to server at {0}: {1}")]
-    LockingFailed(SocketAddr, String),
     #[error("failed to send data: {0}")]
     SendFailed(String),
+    #[error("connection rejected: {0}")]
+    Rejected(RejectReason),
+    #[error("connection attempt timed out")]
+    ConnectTimedOut,
+    #[error("not connected anymore: {0}")]
+    Disconnected(DisconnectReason),
     #[error("socket error: {0}")]
     SocketError(#[from] socket::Error),
     #[error("protocol error: {0}")]
     ProtocolErro(#[from] protocol::Error),
+    #[error("message error: {0}")]
+    MessageError(#[from] message::Error),
+}
+
+impl From<protocol::packet::Error> for Error {
+    fn from(err: protocol::packet::Error) -> Self {
+        Error::ProtocolErro(err.into())
+    }
+}
+
+impl From<protocol::channel::Error> for Error {
+    fn from(err: protocol::channel::Error) -> Self {
+        Error::ProtocolErro(err.into())
+    }
+}
+
+impl From<protocol::security::Error> for Error {
+    fn from(err: protocol::security::Error) -> Self {
+        Error::ProtocolErro(err.into())
+    }
 }
 
 pub fn create_client() -> DisconnectedClient {
@@ -28,46 +76,434 @@ pub fn create_client() -> DisconnectedClient {
 }
 
 #[derive(Default)]
-pub struct DisconnectedClient {}
+pub struct DisconnectedClient {
+    config: ConnectionConfig,
+    conditioner: Option<LinkConditionerConfig>,
+    token: Option<ConnectToken>,
+    messages: MessageRegistry,
+}
 
 impl DisconnectedClient {
+    pub fn with_connection_config(mut self, config: ConnectionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Simulates bad network conditions on the datagrams sent and received
+    /// by the client.
+    pub fn with_link_conditioner(mut self, conditioner: LinkConditionerConfig) -> Self {
+        self.conditioner = Some(conditioner);
+        self
+    }
+
+    /// Connects to a secure server with a token issued for it, encrypting
+    /// every packet with keys derived from the ones the token carries.
+    ///
+    /// A token can only be used for one connection, a new one must be
+    /// issued to connect again.
+    pub fn with_connect_token(mut self, token: ConnectToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Sets the message types exchanged with the server, see
+    /// [`ConnectedClient::send_message`].
+    pub fn with_message_registry(mut self, messages: MessageRegistry) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Binds a UDP socket and starts the handshake with the server.
+    ///
+    /// The returned [`ConnectingClient`] must be polled until the server
+    /// accepts or rejects the connection.
     pub fn connect(
         self,
         client_addr: SocketAddr,
         server_addr: SocketAddr,
-    ) -> Result<ConnectedClient, Error> {
-        let socket =
-            UdpSocket::bind(client_addr).map_err(|err| Error::SocketBindFailed(err.to_string()))?;
-        socket
-            .set_nonblocking(true)
-            .map_err(|err| Error::SocketNonBlockingFailed(err.to_string()))?;
+    ) -> Result<ConnectingClient, Error> {
+        let transport = UdpTransport::bind(client_addr)
+            .map_err(|err| Error::SocketBindFailed(err.to_string()))?;
+        self.connect_with_transport(transport, server_addr)
+    }
+
+    /// Starts the handshake with the server over the given transport.
+    pub fn connect_with_transport(
+        self,
+        transport: impl Transport + 'static,
+        server_addr: SocketAddr,
+    ) -> Result<ConnectingClient, Error> {
+        let transport: Box<dyn Transport> = match self.conditioner.clone() {
+            Some(conditioner) => Box::new(ConditionedTransport::new(transport, conditioner)),
+            None => Box::new(transport),
+        };
+
+        let now = Instant::now();
+        let nonce = generate_handshake_nonce();
+        let mut client = ConnectingClient {
+            link: ServerLink {
+                transport,
+                addr: server_addr,
+                secure: self.token.is_some(),
+                // Set once the server sent its part of the keys
+                cipher: None,
+                pool: BufferPool::default(),
+            },
+            request: PacketBody::connect_request(
+                PROTOCOL_VERSION,
+                self.token.as_ref().map(|token| token.sealed().clone()),
+                nonce,
+            ),
+            nonce,
+            server_nonce: None,
+            rejected: None,
+            config: self.config,
+            conditioner: self.conditioner,
+            token: self.token,
+            messages: self.messages,
+            started_at: now,
+            sent_at: now,
+        };
+        client.send_request()?;
+
+        Ok(client)
+    }
+}
+
+/// Result of polling a [`ConnectingClient`].
+pub enum ConnectionAttempt {
+    /// The server did not answer the handshake yet.
+    Pending(ConnectingClient),
+    Connected(ConnectedClient),
+}
+
+/// Client in the middle of the connect-request / challenge / accept
+/// handshake.
+pub struct ConnectingClient {
+    config: ConnectionConfig,
+    conditioner: Option<LinkConditionerConfig>,
+    token: Option<ConnectToken>,
+    messages: MessageRegistry,
+    link: ServerLink,
+    /// Handshake packet currently being sent to the server.
+    request: PacketBody,
+    /// Part of the connection keys picked by the client.
+    nonce: HandshakeNonce,
+    /// Part of the connection keys picked by the server, from its challenge.
+    server_nonce: Option<HandshakeNonce>,
+    /// Rejection that could not be authenticated, only trusted once the
+    /// handshake times out so that forged ones can not abort it.
+    rejected: Option<RejectReason>,
+    started_at: Instant,
+    sent_at: Instant,
+}
+
+impl ConnectingClient {
+    /// Processes the server answers and resends the handshake packet when
+    /// needed.
+    pub fn poll(mut self) -> Result<ConnectionAttempt, Error> {
+        let mut bodies = self.link.poll().into_iter();
+        while let Some((body, _, encrypted)) = bodies.next() {
+            let authenticated = encrypted || !self.link.secure;
+            match body {
+                PacketBody::Challenge { token, nonce, tag } => {
+                    if let Some(connect_token) = &self.token {
+                        // Anyone may send a challenge to the client, only
+                        // the first one of the server sets the keys
+                        let from_server = tag.is_some_and(|tag| {
+                            connect_token.verify_challenge(&self.nonce, &nonce, token, &tag)
+                        });
+                        if !from_server || self.server_nonce.is_some_and(|first| first != nonce) {
+                            continue;
+                        }
+                        if self.server_nonce.is_none() {
+                            self.server_nonce = Some(nonce);
+                            self.link.cipher =
+                                Some(connect_token.client_cipher(&self.nonce, &nonce));
+                        }
+                    }
+                    self.request = PacketBody::ChallengeResponse { token };
+                    self.send_request()?;
+                }
+                PacketBody::Accepted { version } if authenticated => {
+                    return Ok(ConnectionAttempt::Connected(ConnectedClient {
+                        connection: Box::new(Connection::new(self.config.clone(), Instant::now())),
+                        config: self.config,
+                        conditioner: self.conditioner,
+                        token: self.token,
+                        messages: self.messages,
+                        link: self.link,
+                        version,
+                        // The server may send data right after accepting
+                        received: bodies.collect(),
+                        disconnected: None,
+                    }));
+                }
+                PacketBody::Rejected(reason) if authenticated => {
+                    return Err(Error::Rejected(reason))
+                }
+                // A server refusing the token can not encrypt its answer
+                PacketBody::Rejected(reason) => self.rejected = Some(reason),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.started_at) > self.config.handshake_timeout() {
+            return Err(self
+                .rejected
+                .map_or(Error::ConnectTimedOut, Error::Rejected));
+        }
+        if now.duration_since(self.sent_at) > HANDSHAKE_RESEND_INTERVAL {
+            self.sent_at = now;
+            self.send_request()?;
+        }
 
-        socket
-            .connect(server_addr)
-            .map_err(|err| Error::LockingFailed(server_addr, err.to_string()))?;
+        Ok(ConnectionAttempt::Pending(self))
+    }
+
+    /// Gives up on the connection attempt.
+    pub fn cancel(self) -> DisconnectedClient {
+        DisconnectedClient {
+            config: self.config,
+            conditioner: self.conditioner,
+            token: self.token,
+            messages: self.messages,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.link.transport.local_addr()
+    }
 
-        Ok(ConnectedClient { socket })
+    /// Sends the current handshake packet, only encrypted once the keys of
+    /// the connection are known.
+    fn send_request(&mut self) -> Result<(), Error> {
+        self.link.send(&self.request)
     }
 }
 
+/// Something that happened on a [`ConnectedClient`], returned by
+/// [`ConnectedClient::poll`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Received(ChannelId, Vec<u8>),
+    /// The connection ended, the client should be dropped or disconnected.
+    Disconnected(DisconnectReason),
+}
+
 pub struct ConnectedClient {
-    socket: UdpSocket,
+    config: ConnectionConfig,
+    conditioner: Option<LinkConditionerConfig>,
+    token: Option<ConnectToken>,
+    messages: MessageRegistry,
+    link: ServerLink,
+    version: u16,
+    /// Boxed to keep [`ConnectionAttempt`] small.
+    connection: Box<Connection>,
+    /// Packets received along with the accept, handled by the next poll.
+    received: Vec<(PacketBody, usize, bool)>,
+    disconnected: Option<DisconnectReason>,
 }
 
 impl ConnectedClient {
-    pub fn disconnect(self) -> DisconnectedClient {
-        DisconnectedClient {}
+    /// Ends the connection, notifying the server unless it already ended.
+    pub fn disconnect(mut self) -> DisconnectedClient {
+        if self.disconnected.is_none() {
+            let packet = PacketBody::Disconnect(DisconnectReason::ClientLeft);
+            for _ in 0..DISCONNECT_PACKET_COUNT {
+                let _ = self.link.send(&packet);
+            }
+        }
+
+        DisconnectedClient {
+            config: self.config,
+            conditioner: self.conditioner,
+            token: self.token,
+            messages: self.messages,
+        }
     }
 
-    pub fn send(&self, buf: &[u8]) -> Result<usize, Error> {
-        Ok(socket_send(&self.socket, buf)?)
+    /// Queues the given bytes on a channel, returning how many were queued.
+    ///
+    /// Nothing is sent until the next [`flush`](Self::flush), which packs
+    /// every queued message into as few datagrams as possible.
+    pub fn send(&mut self, channel: impl Into<ChannelId>, buf: &[u8]) -> Result<usize, Error> {
+        if let Some(reason) = self.disconnected {
+            return Err(Error::Disconnected(reason));
+        }
+
+        self.connection.send(channel.into(), buf)?;
+        Ok(buf.len())
     }
 
-    pub fn poll(&self) -> Vec<Vec<u8>> {
-        socket_poll(&self.socket)
+    /// Queues a registered message on a channel, returning how many bytes
+    /// were queued.
+    pub fn send_message<M: NetMessage>(
+        &mut self,
+        channel: impl Into<ChannelId>,
+        message: &M,
+    ) -> Result<usize, Error> {
+        let data = self.messages.encode(message)?;
+        self.send(channel, &data)
+    }
+
+    /// Sends the queued messages, unacknowledged reliable messages and the
+    /// acknowledgement of received packets, or a heartbeat when idle.
+    ///
+    /// Meant to be called once per tick, after the messages of the tick were
+    /// queued.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(reason) = self.disconnected {
+            return Err(Error::Disconnected(reason));
+        }
+
+        let mut buf = self.link.pool.take();
+        let mut result = Ok(());
+        self.connection
+            .write_packets(Instant::now(), &mut buf, |packet| {
+                if result.is_ok() {
+                    result = self.link.send_encoded(packet);
+                }
+            });
+        self.link.pool.give(buf);
+        result
+    }
+
+    /// Receives pending packets and detects timeouts.
+    pub fn poll(&mut self) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+        if self.disconnected.is_some() {
+            return events;
+        }
+
+        let now = Instant::now();
+        let mut bodies = std::mem::take(&mut self.received);
+        bodies.extend(self.link.poll());
+        for (body, len, encrypted) in bodies {
+            if self.link.secure && !encrypted {
+                continue;
+            }
+            self.connection.mark_received(len, now);
+
+            match body {
+                PacketBody::Data { header, messages } => {
+                    self.connection.process(header, messages, now)
+                }
+                PacketBody::Disconnect(reason) => {
+                    self.disconnected = Some(reason);
+                    events.push(ClientEvent::Disconnected(reason));
+                    return events;
+                }
+                _ => {}
+            }
+        }
+        events.extend(
+            self.connection
+                .drain_received()
+                .map(|(channel, data)| ClientEvent::Received(channel, data)),
+        );
+
+        if self.connection.is_timed_out(now) {
+            self.disconnected = Some(DisconnectReason::TimedOut);
+            events.push(ClientEvent::Disconnected(DisconnectReason::TimedOut));
+        }
+
+        events
     }
 
     pub fn addr(&self) -> SocketAddr {
-        self.socket.local_addr().unwrap()
+        self.link.transport.local_addr()
+    }
+
+    /// Protocol version agreed on during the handshake.
+    pub fn protocol_version(&self) -> u16 {
+        self.version
+    }
+
+    /// Message types exchanged with the server, dispatching the received
+    /// ones as events.
+    pub fn messages(&self) -> &MessageRegistry {
+        &self.messages
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.disconnected.is_none()
+    }
+
+    /// Round-trip time, packet loss and bandwidth of the connection.
+    pub fn stats(&self) -> NetworkStats {
+        self.connection.stats(Instant::now())
+    }
+}
+
+/// Transport of a client along with the server address and, when the
+/// connection is secure, the cipher of its packets.
+struct ServerLink {
+    transport: Box<dyn Transport>,
+    addr: SocketAddr,
+    /// Whether the client connects with a token, so only encrypted packets
+    /// are trusted.
+    secure: bool,
+    cipher: Option<PacketCipher>,
+    pool: BufferPool,
+}
+
+impl ServerLink {
+    /// Encodes and sends a packet, encrypted when the connection is secure.
+    fn send(&mut self, body: &PacketBody) -> Result<(), Error> {
+        let mut buf = self.pool.take();
+        let result = body
+            .encode_into(Compression::None, &mut buf)
+            .map_err(Error::from)
+            .and_then(|()| self.send_encoded(&mut buf));
+        self.pool.give(buf);
+        result
+    }
+
+    /// Sends an encoded packet, encrypting it in place first when the
+    /// connection is secure.
+    fn send_encoded(&mut self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        if let Some(cipher) = &mut self.cipher {
+            cipher.seal(buf)?;
+        }
+        socket_send_to(self.transport.as_mut(), buf, self.addr)?;
+        Ok(())
+    }
+
+    /// Receives the packets sent by the server, along with the size of their
+    /// datagram and whether they were encrypted.
+    ///
+    /// Secure clients only get unencrypted challenges and rejections, the
+    /// server can not encrypt those before it knows the keys.
+    fn poll(&mut self) -> Vec<(PacketBody, usize, bool)> {
+        let mut bodies = Vec::new();
+        let mut buf = self.pool.take();
+        while let Some(addr) = socket_recv_from(self.transport.as_mut(), &mut buf) {
+            if addr != self.addr {
+                continue;
+            }
+            let len = buf.len();
+
+            let decrypted = self
+                .cipher
+                .as_mut()
+                .and_then(|cipher| cipher.open(&mut buf))
+                .map(PacketBody::decode);
+            let (body, encrypted) = match decrypted {
+                Some(body) => (body.ok(), true),
+                None => (PacketBody::decode(&buf).ok(), false),
+            };
+            let body = body.filter(|body| {
+                encrypted
+                    || !self.secure
+                    || matches!(body, PacketBody::Challenge { .. } | PacketBody::Rejected(_))
+            });
+            if let Some(body) = body {
+                bodies.push((body, len, encrypted));
+            }
+        }
+        self.pool.give(buf);
+        bodies
     }
 }
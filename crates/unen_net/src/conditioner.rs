@@ -0,0 +1,349 @@
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{protocol::buffer::DATAGRAM_MAX_SIZE, transport::Transport};
+
+/// Smallest extra delay of a reordered datagram, so that it gets overtaken
+/// even without configured latency.
+const REORDER_MIN_DELAY: Duration = Duration::from_millis(20);
+
+/// Bad network conditions simulated by a [`LinkConditioner`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkConditionerConfig {
+    latency: Duration,
+    jitter: Duration,
+    loss: f64,
+    duplicate: f64,
+    reorder: f64,
+    seed: Option<u64>,
+}
+
+impl LinkConditionerConfig {
+    /// Sets the delay added to every datagram.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the maximum random variation added to or removed from the
+    /// latency.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the chance, from 0 to 1, of a datagram being dropped.
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the chance, from 0 to 1, of a datagram being delivered twice.
+    pub fn with_duplicate(mut self, duplicate: f64) -> Self {
+        self.duplicate = duplicate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the chance, from 0 to 1, of a datagram being held back so that
+    /// the following ones overtake it.
+    pub fn with_reorder(mut self, reorder: f64) -> Self {
+        self.reorder = reorder.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Seeds the random generator so that a run can be reproduced, a random
+    /// seed is used otherwise.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    pub fn duplicate(&self) -> f64 {
+        self.duplicate
+    }
+
+    pub fn reorder(&self) -> f64 {
+        self.reorder
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+}
+
+/// Datagram held back by the conditioner.
+#[derive(Debug, Clone)]
+struct Delayed {
+    due: Instant,
+    /// Push order, keeps datagrams due at the same time in order.
+    order: u64,
+    addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+/// Simulated network link dropping, delaying, duplicating and reordering
+/// datagrams.
+///
+/// Datagrams go in with [`push`](Self::push) and come out of
+/// [`pop_due`](Self::pop_due) once their simulated delay is over.
+#[derive(Debug, Clone)]
+pub struct LinkConditioner {
+    config: LinkConditionerConfig,
+    rng: StdRng,
+    queue: Vec<Delayed>,
+    next_order: u64,
+}
+
+impl LinkConditioner {
+    pub fn new(config: LinkConditionerConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+
+        Self {
+            config,
+            rng,
+            queue: Vec::new(),
+            next_order: 0,
+        }
+    }
+
+    pub fn config(&self) -> &LinkConditionerConfig {
+        &self.config
+    }
+
+    /// Passes a datagram through the simulated link.
+    pub fn push(&mut self, addr: SocketAddr, data: Vec<u8>, now: Instant) {
+        if self.rng.random_bool(self.config.loss) {
+            return;
+        }
+
+        if self.rng.random_bool(self.config.duplicate) {
+            let delay = self.delay();
+            self.enqueue(addr, data.clone(), now + delay);
+        }
+        let delay = self.delay();
+        self.enqueue(addr, data, now + delay);
+    }
+
+    /// Takes the datagrams whose delay is over, in delivery order.
+    pub fn pop_due(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let (mut due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|delayed| delayed.due <= now);
+        self.queue = pending;
+
+        due.sort_by_key(|delayed| (delayed.due, delayed.order));
+        due.into_iter()
+            .map(|delayed| (delayed.addr, delayed.data))
+            .collect()
+    }
+
+    /// Amount of datagrams held back.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.config.jitter.as_secs_f64();
+        let offset = if jitter > 0.0 {
+            self.rng.random_range(-jitter..=jitter)
+        } else {
+            0.0
+        };
+        let mut delay =
+            Duration::from_secs_f64((self.config.latency.as_secs_f64() + offset).max(0.0));
+
+        if self.rng.random_bool(self.config.reorder) {
+            delay += self.config.latency.max(REORDER_MIN_DELAY);
+        }
+        delay
+    }
+
+    fn enqueue(&mut self, addr: SocketAddr, data: Vec<u8>, due: Instant) {
+        self.queue.push(Delayed {
+            due,
+            order: self.next_order,
+            addr,
+            data,
+        });
+        self.next_order += 1;
+    }
+}
+
+/// Transport passing the datagrams it sends and receives through a
+/// [`LinkConditioner`] each.
+#[derive(Debug)]
+pub struct ConditionedTransport<T> {
+    inner: T,
+    outgoing: LinkConditioner,
+    incoming: LinkConditioner,
+    /// Received datagrams whose delay is over.
+    ready: VecDeque<(SocketAddr, Vec<u8>)>,
+    /// Receives the datagrams of the inner transport before they are queued.
+    scratch: Vec<u8>,
+}
+
+impl<T: Transport> ConditionedTransport<T> {
+    pub fn new(inner: T, config: LinkConditionerConfig) -> Self {
+        // Both directions must not drop the same datagrams
+        let incoming = match config.seed {
+            Some(seed) => config.clone().with_seed(seed.wrapping_add(1)),
+            None => config.clone(),
+        };
+
+        Self {
+            inner,
+            outgoing: LinkConditioner::new(config),
+            incoming: LinkConditioner::new(incoming),
+            ready: VecDeque::new(),
+            scratch: vec![0; DATAGRAM_MAX_SIZE],
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Sends the outgoing datagrams whose delay is over, failures are
+    /// ignored like any lost datagram.
+    fn release(&mut self, now: Instant) {
+        for (addr, data) in self.outgoing.pop_due(now) {
+            let _ = self.inner.send_to(&data, addr);
+        }
+    }
+}
+
+impl<T: Transport> Transport for ConditionedTransport<T> {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let now = Instant::now();
+        self.outgoing.push(addr, buf.to_vec(), now);
+        self.release(now);
+        Ok(buf.len())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let now = Instant::now();
+        self.release(now);
+
+        if self.scratch.len() < buf.len() {
+            self.scratch.resize(buf.len(), 0);
+        }
+        while let Ok((len, addr)) = self.inner.recv_from(&mut self.scratch) {
+            self.incoming.push(addr, self.scratch[..len].to_vec(), now);
+        }
+        self.ready.extend(self.incoming.pop_due(now));
+
+        let (addr, data) = self
+            .ready
+            .pop_front()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, addr))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    const ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1234));
+
+    fn run(config: LinkConditionerConfig, count: u8) -> Vec<u8> {
+        let mut conditioner = LinkConditioner::new(config);
+        let start = Instant::now();
+        for i in 0..count {
+            conditioner.push(ADDR, vec![i], start);
+        }
+        conditioner
+            .pop_due(start + Duration::from_secs(1))
+            .into_iter()
+            .map(|(_, data)| data[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut conditioner = LinkConditioner::new(
+            LinkConditionerConfig::default().with_latency(Duration::from_millis(50)),
+        );
+        let start = Instant::now();
+        conditioner.push(ADDR, vec![1], start);
+
+        assert!(
+            conditioner
+                .pop_due(start + Duration::from_millis(49))
+                .is_empty(),
+            "should hold datagrams back for the latency"
+        );
+        assert_eq!(
+            conditioner.pop_due(start + Duration::from_millis(50)),
+            vec![(ADDR, vec![1])],
+            "should deliver once the latency is over"
+        );
+    }
+
+    #[test]
+    fn test_bad_conditions() {
+        let config = LinkConditionerConfig::default().with_seed(7);
+
+        let lossy = run(config.clone().with_loss(0.5), 200);
+        assert!(
+            (60..140).contains(&lossy.len()),
+            "should drop about half of the datagrams"
+        );
+
+        let duplicated = run(config.clone().with_duplicate(0.5), 200);
+        assert!(
+            (260..340).contains(&duplicated.len()),
+            "should duplicate about half of the datagrams"
+        );
+
+        let reordered = run(config.clone().with_reorder(0.5), 200);
+        assert!(
+            reordered.windows(2).any(|pair| pair[0] > pair[1]),
+            "should reorder datagrams"
+        );
+
+        let config = config
+            .with_loss(0.2)
+            .with_duplicate(0.2)
+            .with_reorder(0.2)
+            .with_jitter(Duration::from_millis(10));
+        assert_eq!(
+            run(config.clone(), 100),
+            run(config, 100),
+            "same seed should give the same run"
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use bincode::{Decode, Encode};
+use thiserror::Error;
+
+use crate::{
+    client::HANDSHAKE_TIMEOUT,
+    protocol::{
+        body::PacketBody,
+        channel::{self, ChannelConfig, ChannelId, ChannelKind, DefaultChannel},
+        fragment::{DEFAULT_MAX_REASSEMBLY_BUFFERS, DEFAULT_REASSEMBLY_TIMEOUT},
+        packet::Compression,
+        reliability::{AckHeader, Message, ReliableEndpoint},
+    },
+    stats::{NetworkStats, Throughput},
+};
+
+/// How many times a disconnect packet is sent, since it is never
+/// acknowledged.
+pub const DISCONNECT_PACKET_COUNT: usize = 3;
+
+/// Why a connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Error)]
+pub enum DisconnectReason {
+    #[error("nothing was received from the peer for too long")]
+    TimedOut,
+    #[error("client disconnected")]
+    ClientLeft,
+    #[error("server stopped")]
+    ServerStopped,
+    #[error("kicked by the server")]
+    Kicked,
+}
+
+/// Timing and channel configuration shared by clients and servers, both
+/// ends of a connection must configure the same channels.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    heartbeat_interval: Duration,
+    timeout: Duration,
+    handshake_timeout: Duration,
+    resend_interval: Duration,
+    max_reassembly_buffers: usize,
+    reassembly_timeout: Duration,
+    channels: BTreeMap<ChannelId, ChannelConfig>,
+}
+
+impl ConnectionConfig {
+    /// Sets after how long without sending anything a heartbeat is sent to
+    /// keep the connection alive.
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Sets after how long without receiving anything the connection is
+    /// dropped with [`DisconnectReason::TimedOut`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long a handshake may take before the client gives up, and
+    /// the server forgets about it.
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets after how long without acknowledgement a reliable message is
+    /// sent again.
+    pub fn with_resend_interval(mut self, resend_interval: Duration) -> Self {
+        self.resend_interval = resend_interval;
+        self
+    }
+
+    /// Sets how many incomplete fragmented messages are kept at once per
+    /// peer, further ones are dropped.
+    pub fn with_max_reassembly_buffers(mut self, max_reassembly_buffers: usize) -> Self {
+        self.max_reassembly_buffers = max_reassembly_buffers;
+        self
+    }
+
+    /// Sets after how long an incomplete fragmented message is discarded.
+    pub fn with_reassembly_timeout(mut self, reassembly_timeout: Duration) -> Self {
+        self.reassembly_timeout = reassembly_timeout;
+        self
+    }
+
+    /// Adds a channel, or replaces the configuration of an existing one.
+    pub fn with_channel(mut self, id: impl Into<ChannelId>, config: ChannelConfig) -> Self {
+        self.channels.insert(id.into(), config);
+        self
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    pub fn resend_interval(&self) -> Duration {
+        self.resend_interval
+    }
+
+    pub fn max_reassembly_buffers(&self) -> usize {
+        self.max_reassembly_buffers
+    }
+
+    pub fn reassembly_timeout(&self) -> Duration {
+        self.reassembly_timeout
+    }
+
+    pub fn channel(&self, id: impl Into<ChannelId>) -> Option<&ChannelConfig> {
+        self.channels.get(&id.into())
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(250),
+            timeout: Duration::from_secs(10),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            resend_interval: Duration::from_millis(100),
+            max_reassembly_buffers: DEFAULT_MAX_REASSEMBLY_BUFFERS,
+            reassembly_timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            channels: BTreeMap::from([
+                (
+                    DefaultChannel::Reliable.into(),
+                    ChannelConfig::new(ChannelKind::ReliableOrdered),
+                ),
+                (
+                    DefaultChannel::Unreliable.into(),
+                    ChannelConfig::new(ChannelKind::Unreliable),
+                ),
+            ]),
+        }
+    }
+}
+
+/// Per-peer connection state, shared by the client and every server-side
+/// client entry.
+#[derive(Debug, Clone)]
+pub(crate) struct Connection {
+    config: ConnectionConfig,
+    last_sent: Instant,
+    last_received: Instant,
+    endpoint: ReliableEndpoint,
+    sent: Throughput,
+    received: Throughput,
+}
+
+impl Connection {
+    pub fn new(config: ConnectionConfig, now: Instant) -> Self {
+        Self {
+            endpoint: ReliableEndpoint::new(
+                config.resend_interval,
+                config.channels.iter().map(|(&id, &channel)| (id, channel)),
+            )
+            .with_reassembly(config.max_reassembly_buffers, config.reassembly_timeout),
+            config,
+            last_sent: now,
+            last_received: now,
+            sent: Throughput::new(now),
+            received: Throughput::new(now),
+        }
+    }
+
+    pub fn mark_sent(&mut self, bytes: usize, now: Instant) {
+        self.last_sent = now;
+        self.sent.record(bytes, now);
+    }
+
+    pub fn mark_received(&mut self, bytes: usize, now: Instant) {
+        self.last_received = now;
+        self.received.record(bytes, now);
+    }
+
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    /// Queues a message, sent by the next [`write_packets`](Self::write_packets).
+    pub fn send(&mut self, channel: ChannelId, data: &[u8]) -> Result<(), channel::Error> {
+        self.endpoint.send(channel, data)
+    }
+
+    /// Encodes the packets to transmit now, or a heartbeat when nothing was
+    /// sent for a while, into `buf` and hands each one to `send`.
+    pub fn write_packets(
+        &mut self,
+        now: Instant,
+        buf: &mut Vec<u8>,
+        mut send: impl FnMut(&mut Vec<u8>),
+    ) {
+        let mut bodies = self.endpoint.write_packets(now);
+        if bodies.is_empty() && self.needs_heartbeat(now) {
+            bodies.push(self.endpoint.write_heartbeat(now));
+        }
+
+        // Packed messages always fit a packet, so encoding can not fail
+        for body in &bodies {
+            if body.encode_into(self.compression(body), buf).is_ok() {
+                self.mark_sent(buf.len(), now);
+                send(buf);
+            }
+        }
+    }
+
+    /// Compression of a packet, the one of its channels asking for it.
+    fn compression(&self, body: &PacketBody) -> Compression {
+        let PacketBody::Data { messages, .. } = body else {
+            return Compression::None;
+        };
+
+        messages
+            .iter()
+            .filter_map(|message| self.config.channel(ChannelId::new(message.channel)))
+            .map(ChannelConfig::compression)
+            .find(|compression| *compression != Compression::None)
+            .unwrap_or_default()
+    }
+
+    /// Handles a received data packet, the messages it carried are then
+    /// returned by [`drain_received`](Self::drain_received).
+    pub fn process(&mut self, header: AckHeader, messages: Vec<Message>, now: Instant) {
+        self.endpoint.process(header, messages, now);
+    }
+
+    pub fn drain_received(&mut self) -> impl Iterator<Item = (ChannelId, Vec<u8>)> + '_ {
+        self.endpoint.drain_received()
+    }
+
+    pub fn needs_heartbeat(&self, now: Instant) -> bool {
+        now.duration_since(self.last_sent) >= self.config.heartbeat_interval
+    }
+
+    pub fn is_timed_out(&self, now: Instant) -> bool {
+        now.duration_since(self.last_received) >= self.config.timeout
+    }
+
+    pub fn stats(&self, now: Instant) -> NetworkStats {
+        let (sent_bytes_per_sec, sent_packets_per_sec) = self.sent.rates(now);
+        let (received_bytes_per_sec, received_packets_per_sec) = self.received.rates(now);
+        NetworkStats {
+            rtt: self.endpoint.rtt(),
+            jitter: self.endpoint.jitter(),
+            packet_loss: self.endpoint.packet_loss(),
+            sent_bytes_per_sec,
+            sent_packets_per_sec,
+            received_bytes_per_sec,
+            received_packets_per_sec,
+            resent_messages: self.endpoint.resent_count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::packet::Packet;
+
+    fn write_packets(connection: &mut Connection, now: Instant) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        connection.write_packets(now, &mut Vec::new(), |buf| {
+            packets.push(Packet::from_bytes(buf.clone()))
+        });
+        packets
+    }
+
+    #[test]
+    fn test_heartbeat_and_timeout() {
+        let config = ConnectionConfig::default()
+            .with_heartbeat_interval(Duration::from_millis(100))
+            .with_timeout(Duration::from_millis(500));
+        let start = Instant::now();
+        let mut connection = Connection::new(config, start);
+
+        let later = start + Duration::from_millis(150);
+        assert!(
+            connection.needs_heartbeat(later),
+            "should need a heartbeat after the interval"
+        );
+        connection.mark_sent(0, later);
+        assert!(
+            !connection.needs_heartbeat(later),
+            "should not need a heartbeat right after sending"
+        );
+
+        assert!(
+            !connection.is_timed_out(later),
+            "should not time out before the timeout"
+        );
+        assert!(
+            connection.is_timed_out(start + Duration::from_millis(500)),
+            "should time out without receiving anything"
+        );
+        connection.mark_received(0, start + Duration::from_millis(400));
+        assert!(
+            !connection.is_timed_out(start + Duration::from_millis(500)),
+            "receiving should reset the timeout"
+        );
+    }
+
+    #[test]
+    fn test_messages_and_acks_share_a_packet() {
+        let now = Instant::now();
+        let mut client = Connection::new(ConnectionConfig::default(), now);
+        let mut server = Connection::new(ConnectionConfig::default(), now);
+
+        server
+            .send(DefaultChannel::Reliable.into(), b"snapshot")
+            .expect("should be able to queue");
+        for packet in write_packets(&mut server, now) {
+            let Ok(PacketBody::Data { header, messages }) = PacketBody::from_packet(&packet) else {
+                panic!("should write data packets");
+            };
+            client.process(header, messages, now);
+        }
+
+        for input in 0..5u8 {
+            client
+                .send(DefaultChannel::Unreliable.into(), &[input])
+                .expect("should be able to queue");
+        }
+        let packets = write_packets(&mut client, now);
+        assert_eq!(packets.len(), 1, "should pack everything in one packet");
+        let Ok(PacketBody::Data { header, messages }) = PacketBody::from_packet(&packets[0]) else {
+            panic!("should write data packets");
+        };
+        assert_eq!(messages.len(), 5, "should carry every input");
+        assert_eq!(header.ack, 0, "should acknowledge the received packet");
+    }
+
+    #[test]
+    fn test_channel_compression() {
+        let snapshots = ChannelId::new(2);
+        let config = ConnectionConfig::default().with_channel(
+            snapshots,
+            ChannelConfig::new(ChannelKind::UnreliableSequenced).with_compression(Compression::Lz4),
+        );
+        let now = Instant::now();
+        let mut connection = Connection::new(config, now);
+
+        let snapshot = [0u8; 800];
+        connection
+            .send(DefaultChannel::Unreliable.into(), &snapshot)
+            .expect("should be able to queue");
+        let packets = write_packets(&mut connection, now);
+        assert!(
+            !packets[0].is_compressed(),
+            "should not compress channels without compression"
+        );
+
+        connection
+            .send(snapshots, &snapshot)
+            .expect("should be able to queue");
+        let packets = write_packets(&mut connection, now);
+        assert!(
+            packets[0].is_compressed(),
+            "should compress packets of compressed channels"
+        );
+        assert!(
+            packets[0].as_bytes().len() < snapshot.len() / 2,
+            "should send fewer bytes"
+        );
+    }
+}
@@ -1,30 +1,184 @@
+// Lets `#[derive(NetMessage)]` refer to this crate from its own tests
+extern crate self as unen_net;
+
 pub mod client;
+pub mod conditioner;
+pub mod connection;
+pub mod message;
+pub mod prediction;
 pub mod protocol;
+pub mod replication;
 pub mod server;
 pub mod socket;
+pub mod stats;
+pub mod transport;
+
+#[cfg(feature = "derive")]
+pub use unen_net_derive::NetMessage;
 
 #[cfg(test)]
 mod tests {
     use super::client::*;
     use super::server::*;
 
-    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use super::client::Error as ClientError;
+    use super::conditioner::LinkConditionerConfig;
+    use super::connection::{ConnectionConfig, DisconnectReason};
+    use super::message::{ClientMessage, Error as MessageError, MessageRegistry, ServerMessage};
+    use super::protocol::body::PacketBody;
+    use super::protocol::channel::DefaultChannel;
+    use super::protocol::handshake::RejectReason;
+    use super::protocol::security::{generate_handshake_nonce, generate_key, TokenIssuer};
+    use super::transport::{MemoryTransport, Transport};
+
+    use bincode::{Decode, Encode};
+    use unen_core::prelude::create_engine;
+    use unen_event::prelude::{EngineEvent, EventBox, EventEmitter, EventHandler, EventManager};
+    use unen_net_derive::NetMessage;
+    use unen_runner::prelude::MininalRunner;
+
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+    use std::sync::mpsc;
     use std::thread;
     use std::time::Duration;
 
     const LOCAL_ADDR: SocketAddr =
         SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0));
 
+    /// Drives the handshake of a new client until the server answers,
+    /// returning the client and the id the server assigned to it.
+    fn try_connect(
+        server: &mut ListeningServer,
+    ) -> Result<(ConnectedClient, ClientId), ClientError> {
+        try_connect_with(server, create_client())
+    }
+
+    fn try_connect_with(
+        server: &mut ListeningServer,
+        client: DisconnectedClient,
+    ) -> Result<(ConnectedClient, ClientId), ClientError> {
+        let mut attempt = client
+            .connect(LOCAL_ADDR, server.addr())
+            .expect("should be able to start connecting");
+        let mut id = None;
+        for _ in 0..1000 {
+            for event in server.poll() {
+                if let ServerEvent::Connected(connected) = event {
+                    id = Some(connected);
+                }
+            }
+            match attempt.poll()? {
+                ConnectionAttempt::Connected(client) => {
+                    return Ok((client, id.expect("server should report the client")))
+                }
+                ConnectionAttempt::Pending(client) => attempt = client,
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("handshake should have completed");
+    }
+
+    fn connect(server: &mut ListeningServer) -> (ConnectedClient, ClientId) {
+        try_connect(server).expect("should be able to connect to server")
+    }
+
+    #[test]
+    fn handshake() {
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let (client, id) = connect(&mut server);
+
+        let entry = server.client(id).expect("server should know the client");
+        assert_eq!(
+            entry.addr(),
+            client.addr(),
+            "should track the client address"
+        );
+    }
+
+    #[test]
+    fn client_ids() {
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let (_, first) = connect(&mut server);
+        let (_, second) = connect(&mut server);
+        assert_ne!(first, second, "should assign distinct ids");
+
+        server
+            .disconnect(first)
+            .expect("should know the first client");
+        assert_eq!(
+            server.poll(),
+            vec![ServerEvent::Disconnected(first, DisconnectReason::Kicked)],
+            "should report the disconnection"
+        );
+
+        let (_, third) = connect(&mut server);
+        assert!(third > second, "should never reuse ids");
+        assert_eq!(server.client_count(), 2, "should have two clients left");
+    }
+
+    #[test]
+    fn reject_server_full() {
+        let mut server = create_server()
+            .with_max_clients(1)
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let _first = connect(&mut server);
+
+        assert!(
+            matches!(
+                try_connect(&mut server),
+                Err(ClientError::Rejected(RejectReason::ServerFull))
+            ),
+            "should reject clients past the limit"
+        );
+    }
+
+    #[test]
+    fn reject_version_mismatch() {
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let socket = UdpSocket::bind(LOCAL_ADDR).expect("should be able to bind");
+        let request = PacketBody::connect_request(0, None, generate_handshake_nonce())
+            .encode()
+            .expect("should be able to encode");
+        socket
+            .send_to(request.as_bytes(), server.addr())
+            .expect("should be able to send");
+
+        thread::sleep(Duration::from_millis(10));
+        server.poll();
+
+        let mut buf = [0u8; 64];
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .expect("should be able to set timeout");
+        let len = socket.recv(&mut buf).expect("should receive an answer");
+        let answer = PacketBody::decode(&buf[..len]).expect("should decode");
+        assert!(
+            matches!(
+                answer,
+                PacketBody::Rejected(RejectReason::VersionMismatch { client: 0, .. })
+            ),
+            "should reject unsupported versions"
+        );
+    }
+
     #[test]
     fn send_to_server() {
-        let server = create_server()
+        let mut server = create_server()
             .listen(LOCAL_ADDR)
             .expect("should be able to listen");
-        let client = create_client()
-            .connect(LOCAL_ADDR, server.addr())
-            .expect("should be able to connect to server");
+        let (mut client, id) = connect(&mut server);
 
-        let sent_bytes = client.send(b"hello!").expect("should be able to send");
+        let sent_bytes = client
+            .send(DefaultChannel::Reliable, b"hello!")
+            .expect("should be able to send");
+        client.flush().expect("should be able to flush");
 
         assert_eq!(sent_bytes, 6, "should have sent 6 bytes");
 
@@ -39,30 +193,30 @@ mod tests {
 
         assert_eq!(received_data.len(), 1, "should have received 1 datagram");
 
-        let (addres, bytes) = received_data.pop().unwrap();
+        let ServerEvent::Received(sender, channel, bytes) = received_data.pop().unwrap() else {
+            panic!("should have received data");
+        };
 
+        assert_eq!(sender, id, "should have been received from client");
         assert_eq!(
-            addres,
-            client.addr(),
-            "should have been received from client"
+            channel,
+            DefaultChannel::Reliable.into(),
+            "should keep the channel"
         );
         assert_eq!(bytes.as_slice(), b"hello!", "should have received 'hello!'");
     }
 
     #[test]
     fn send_to_client() {
-        let server = create_server()
+        let mut server = create_server()
             .listen(LOCAL_ADDR)
             .expect("should be able to listen");
-        let client = create_client()
-            .connect(LOCAL_ADDR, server.addr())
-            .expect("should be able to connect to server");
-
-        let client_addr = client.addr();
+        let (mut client, id) = connect(&mut server);
 
         let sent_bytes = server
-            .send_to(b"hello :D", client_addr)
+            .send(id, DefaultChannel::Unreliable, b"hello :D")
             .expect("should be able to send");
+        server.flush();
 
         assert_eq!(sent_bytes, 8, "should have sent 8 bytes");
 
@@ -77,7 +231,9 @@ mod tests {
 
         assert_eq!(received_data.len(), 1, "should have received 1 datagram");
 
-        let bytes = received_data.pop().unwrap();
+        let ClientEvent::Received(_, bytes) = received_data.pop().unwrap() else {
+            panic!("should have received data");
+        };
 
         assert_eq!(
             bytes.as_slice(),
@@ -85,4 +241,787 @@ mod tests {
             "should have received 'hello :D'"
         );
     }
+
+    #[test]
+    fn memory_transport() {
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        let server_addr = server_transport.local_addr();
+        let mut server = create_server().listen_with_transport(server_transport);
+        let attempt = create_client()
+            .connect_with_transport(client_transport, server_addr)
+            .expect("should be able to start connecting");
+
+        let mut attempt = ConnectionAttempt::Pending(attempt);
+        let mut server_events = Vec::new();
+        while let ConnectionAttempt::Pending(pending) = attempt {
+            server_events.extend(server.poll());
+            attempt = pending.poll().expect("should be able to connect");
+        }
+        let ConnectionAttempt::Connected(mut client) = attempt else {
+            unreachable!();
+        };
+        let [ServerEvent::Connected(id)] = server_events[..] else {
+            panic!("server should report the client");
+        };
+
+        client
+            .send(DefaultChannel::Reliable, b"ping")
+            .expect("should be able to send");
+        client.flush().expect("should be able to flush");
+        assert_eq!(
+            server.poll(),
+            vec![ServerEvent::Received(
+                id,
+                DefaultChannel::Reliable.into(),
+                b"ping".to_vec()
+            )],
+            "server should receive without waiting"
+        );
+
+        server
+            .send(id, DefaultChannel::Reliable, b"pong")
+            .expect("should be able to send");
+        server.flush();
+        assert_eq!(
+            client.poll(),
+            vec![ClientEvent::Received(
+                DefaultChannel::Reliable.into(),
+                b"pong".to_vec()
+            )],
+            "client should receive without waiting"
+        );
+    }
+
+    #[test]
+    fn receive_along_with_accept() {
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        let server_addr = server_transport.local_addr();
+        let mut server = create_server().listen_with_transport(server_transport);
+        let mut attempt = ConnectionAttempt::Pending(
+            create_client()
+                .connect_with_transport(client_transport, server_addr)
+                .expect("should be able to start connecting"),
+        );
+
+        while let ConnectionAttempt::Pending(pending) = attempt {
+            for event in server.poll() {
+                if let ServerEvent::Connected(id) = event {
+                    server
+                        .send(id, DefaultChannel::Unreliable, b"welcome")
+                        .expect("should be able to send");
+                    server.flush();
+                }
+            }
+            attempt = pending.poll().expect("should be able to connect");
+        }
+        let ConnectionAttempt::Connected(mut client) = attempt else {
+            unreachable!();
+        };
+
+        assert_eq!(
+            client.poll(),
+            vec![ClientEvent::Received(
+                DefaultChannel::Unreliable.into(),
+                b"welcome".to_vec()
+            )],
+            "should keep the packets received with the accept"
+        );
+    }
+
+    /// Drives the handshake of a client over a memory transport, without any
+    /// delay.
+    fn connect_in_memory(
+        server: StoppedServer,
+        client: DisconnectedClient,
+    ) -> Result<(ListeningServer, ConnectedClient, ClientId), ClientError> {
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        connect_over(server, client, client_transport, server_transport)
+    }
+
+    fn connect_over(
+        server: StoppedServer,
+        client: DisconnectedClient,
+        client_transport: MemoryTransport,
+        server_transport: MemoryTransport,
+    ) -> Result<(ListeningServer, ConnectedClient, ClientId), ClientError> {
+        let server_addr = server_transport.local_addr();
+        let mut server = server.listen_with_transport(server_transport);
+        let mut attempt = client
+            .connect_with_transport(client_transport, server_addr)
+            .expect("should be able to start connecting");
+
+        let mut id = None;
+        loop {
+            for event in server.poll() {
+                if let ServerEvent::Connected(connected) = event {
+                    id = Some(connected);
+                }
+            }
+            match attempt.poll()? {
+                ConnectionAttempt::Connected(client) => {
+                    server.poll();
+                    return Ok((server, client, id.expect("server should report the client")));
+                }
+                ConnectionAttempt::Pending(client) => attempt = client,
+            }
+        }
+    }
+
+    #[derive(Debug, Encode, Decode, NetMessage)]
+    struct Ping(u32);
+
+    #[derive(Debug, Encode, Decode, NetMessage)]
+    struct Cheat;
+
+    #[test]
+    fn typed_messages() {
+        let messages = MessageRegistry::default().with_message::<Ping>();
+        let (mut server, mut client, id) = connect_in_memory(
+            create_server().with_message_registry(messages.clone()),
+            create_client().with_message_registry(messages.with_message::<Cheat>()),
+        )
+        .expect("should connect");
+        let emitter = EventManager::default().get_emitter();
+
+        client
+            .send_message(DefaultChannel::Reliable, &Ping(1))
+            .expect("should be able to send");
+        client
+            .send_message(DefaultChannel::Reliable, &Cheat)
+            .expect("should be able to send");
+        client.flush().expect("should be able to flush");
+
+        let received: Vec<_> = server
+            .poll()
+            .into_iter()
+            .filter_map(|event| match event {
+                ServerEvent::Received(_, _, data) => Some(data),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(received.len(), 2, "should receive both messages");
+        assert!(
+            server
+                .messages()
+                .dispatch_from_client(id, &received[0], &emitter)
+                .is_ok(),
+            "should dispatch registered messages"
+        );
+        assert!(
+            matches!(
+                server
+                    .messages()
+                    .dispatch_from_client(id, &received[1], &emitter),
+                Err(MessageError::UnregisteredMessage(_))
+            ),
+            "should reject messages the server did not register"
+        );
+        assert!(
+            server
+                .send_message(id, DefaultChannel::Reliable, &Cheat)
+                .is_err(),
+            "should not send unregistered messages"
+        );
+    }
+
+    /// Records the network events reaching the engine bus.
+    struct Recorder(mpsc::Sender<String>);
+
+    impl EventHandler for Recorder {
+        fn handle(&mut self, event: &EventBox) -> bool {
+            if let Some(event) = event.downcast_ref::<NetworkServerEvent>() {
+                let _ = self.0.send(format!("{event:?}"));
+            }
+            if let Some(event) = event.downcast_ref::<NetworkClientEvent>() {
+                let _ = self.0.send(format!("{event:?}"));
+            }
+            if let Some(ClientMessage { client, message }) =
+                event.downcast_ref::<ClientMessage<Ping>>()
+            {
+                let _ = self.0.send(format!("{client} sent {message:?}"));
+            }
+            if let Some(ServerMessage(message)) = event.downcast_ref::<ServerMessage<Ping>>() {
+                let _ = self.0.send(format!("server sent {message:?}"));
+            }
+            false
+        }
+    }
+
+    #[test]
+    fn engine_handlers() {
+        let messages = MessageRegistry::default().with_message::<Ping>();
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        let server_addr = server_transport.local_addr();
+        let server = create_server()
+            .with_message_registry(messages.clone())
+            .listen_with_transport(server_transport);
+        let client = create_client()
+            .with_message_registry(messages)
+            .connect_with_transport(client_transport, server_addr)
+            .expect("should be able to start connecting");
+
+        let (sender, recorded) = mpsc::channel();
+        let mut manager = EventManager::default();
+        manager.add_handler(NetworkServerHandler::new(server));
+        manager.add_handler(NetworkClientHandler::new(client));
+        manager.add_handler(Recorder(sender));
+        let emitter = manager.get_emitter();
+        let mut update = || {
+            for _ in 0..5 {
+                emitter.emit(EngineEvent::Update);
+                manager.step();
+            }
+            recorded.try_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            update(),
+            vec!["ClientConnected(ClientId(0))", "Connected"],
+            "should report the connection on both ends"
+        );
+        emitter.emit(SendToServer::new(DefaultChannel::Reliable, Ping(1)));
+        assert_eq!(
+            update(),
+            vec!["#0 sent Ping(1)"],
+            "server should receive typed messages"
+        );
+        emitter.emit(SendToClient::new(
+            Recipient::All,
+            DefaultChannel::Reliable,
+            Ping(2),
+        ));
+        assert_eq!(
+            update(),
+            vec!["server sent Ping(2)"],
+            "client should receive typed messages"
+        );
+        emitter.emit(EngineEvent::Stopping);
+        assert_eq!(
+            update(),
+            vec!["Disconnected(ClientLeft)"],
+            "client should disconnect when the engine stops"
+        );
+    }
+
+    #[test]
+    fn fixed_update_server() {
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        let server_addr = server_transport.local_addr();
+        let server = create_server().listen_with_transport(server_transport);
+        let client = create_client()
+            .connect_with_transport(client_transport, server_addr)
+            .expect("should be able to start connecting");
+
+        let (sender, recorded) = mpsc::channel();
+        let mut manager = EventManager::default();
+        manager.add_handler(NetworkServerHandler::new(server).with_fixed_update());
+        manager.add_handler(NetworkClientHandler::new(client));
+        manager.add_handler(Recorder(sender));
+        let emitter = manager.get_emitter();
+
+        for _ in 0..5 {
+            emitter.emit(EngineEvent::Update);
+            manager.step();
+        }
+        assert!(
+            recorded.try_iter().next().is_none(),
+            "should not poll the server on frames"
+        );
+        for tick in 0..5 {
+            emitter.emit(EngineEvent::FixedUpdate { tick });
+            emitter.emit(EngineEvent::Update);
+            manager.step();
+        }
+        assert_eq!(
+            recorded.try_iter().collect::<Vec<_>>(),
+            vec!["ClientConnected(ClientId(0))", "Connected"],
+            "should poll the server on ticks"
+        );
+    }
+
+    /// Plays a ping exchange once connected, then stops the engine.
+    struct PingPong {
+        emitter: Option<EventEmitter>,
+        frames: usize,
+    }
+
+    impl EventHandler for PingPong {
+        fn handle(&mut self, event: &EventBox) -> bool {
+            let Some(emitter) = &self.emitter else {
+                return false;
+            };
+            if let Some(NetworkClientEvent::Connected) = event.downcast_ref() {
+                emitter.emit(SendToServer::new(DefaultChannel::Reliable, Ping(1)));
+            }
+            if event.downcast_ref::<ClientMessage<Ping>>().is_some() {
+                emitter.emit(SendToClient::new(
+                    Recipient::All,
+                    DefaultChannel::Reliable,
+                    Ping(2),
+                ));
+            }
+            if event.downcast_ref::<ServerMessage<Ping>>().is_some() {
+                emitter.emit(EngineEvent::Shutdown);
+            }
+            if let Some(EngineEvent::Update) = event.downcast_ref() {
+                self.frames += 1;
+                // Gives up rather than hanging the tests
+                if self.frames == 5000 {
+                    emitter.emit(EngineEvent::Shutdown);
+                }
+            }
+            false
+        }
+
+        fn attach(&mut self, emitter: EventEmitter) {
+            self.emitter = Some(emitter);
+        }
+    }
+
+    #[test]
+    fn engine_loop() {
+        let messages = MessageRegistry::default().with_message::<Ping>();
+        let (client_transport, server_transport) = MemoryTransport::pair();
+        let server_addr = server_transport.local_addr();
+        let server = create_server()
+            .with_message_registry(messages.clone())
+            .listen_with_transport(server_transport);
+        let client = create_client()
+            .with_message_registry(messages)
+            .connect_with_transport(client_transport, server_addr)
+            .expect("should be able to start connecting");
+
+        let (sender, recorded) = mpsc::channel();
+        let _ = create_engine()
+            .set_runner(MininalRunner::default())
+            .add_event_handler(NetworkServerHandler::new(server))
+            .add_event_handler(NetworkClientHandler::new(client))
+            .add_event_handler(Recorder(sender))
+            .add_event_handler(PingPong {
+                emitter: None,
+                frames: 0,
+            })
+            .start()
+            .stop();
+
+        assert_eq!(
+            recorded.try_iter().collect::<Vec<_>>(),
+            vec![
+                "ClientConnected(ClientId(0))",
+                "Connected",
+                "#0 sent Ping(1)",
+                "server sent Ping(2)",
+                "Disconnected(ClientLeft)"
+            ],
+            "should be driven by the runner"
+        );
+    }
+
+    #[test]
+    fn secure_connection() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(42, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let (mut server, mut client, id) = connect_in_memory(
+            create_server().with_private_key(private_key),
+            create_client().with_connect_token(token),
+        )
+        .expect("should connect with a valid token");
+
+        assert_eq!(
+            server.client(id).and_then(ClientEntry::user_id),
+            Some(42),
+            "should know the user of the token"
+        );
+
+        client
+            .send(DefaultChannel::Reliable, b"secret")
+            .expect("should be able to send");
+        client.flush().expect("should be able to flush");
+        assert_eq!(
+            server.poll(),
+            vec![ServerEvent::Received(
+                id,
+                DefaultChannel::Reliable.into(),
+                b"secret".to_vec()
+            )],
+            "should decrypt the messages"
+        );
+    }
+
+    #[test]
+    fn reject_invalid_token() {
+        let private_key = generate_key();
+        let result = connect_in_memory(
+            create_server().with_private_key(private_key),
+            create_client(),
+        );
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::Rejected(RejectReason::InvalidConnectToken))
+            ),
+            "should reject clients without a token"
+        );
+
+        let forged = TokenIssuer::new(generate_key())
+            .issue(42, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let result = connect_in_memory(
+            create_server().with_private_key(private_key),
+            create_client()
+                .with_connection_config(short_handshake())
+                .with_connect_token(forged),
+        );
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::Rejected(RejectReason::InvalidConnectToken))
+            ),
+            "should reject tokens issued with another key"
+        );
+    }
+
+    #[test]
+    fn ignore_forged_rejection() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(42, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let (client_transport, mut server_transport) = MemoryTransport::pair();
+        let forged = PacketBody::Rejected(RejectReason::ServerFull)
+            .encode()
+            .expect("should be able to encode");
+        server_transport
+            .send_to(forged.as_bytes(), server_transport.peer_addr())
+            .expect("should be able to send");
+
+        let result = connect_over(
+            create_server().with_private_key(private_key),
+            create_client().with_connect_token(token),
+            client_transport,
+            server_transport,
+        );
+        assert!(
+            result.is_ok(),
+            "secure clients should ignore unencrypted rejections"
+        );
+    }
+
+    #[test]
+    fn ignore_forged_challenge() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(42, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let opened = token
+            .sealed()
+            .open(&private_key)
+            .expect("should be able to open the token");
+        let (client_transport, mut server_transport) = MemoryTransport::pair();
+        let client_addr = server_transport.peer_addr();
+        let attempt = create_client()
+            .with_connect_token(token)
+            .connect_with_transport(client_transport, server_transport.local_addr())
+            .expect("should be able to start connecting");
+
+        // Plays the server by hand, with a forged challenge on both sides of
+        // the genuine one
+        let mut buf = vec![0; 2048];
+        let (len, _) = server_transport
+            .recv_from(&mut buf)
+            .expect("should receive the connect request");
+        let Ok(PacketBody::ConnectRequest { nonce, .. }) = PacketBody::decode(&buf[..len]) else {
+            panic!("should start with a connect request");
+        };
+        let server_nonce = generate_handshake_nonce();
+        let forged = PacketBody::Challenge {
+            token: 1,
+            nonce: generate_handshake_nonce(),
+            tag: Some([0; 16]),
+        };
+        let genuine = PacketBody::Challenge {
+            token: 2,
+            nonce: server_nonce,
+            tag: Some(opened.challenge_tag(&nonce, &server_nonce, 2)),
+        };
+        for body in [&forged, &genuine, &forged] {
+            let packet = body.encode().expect("should be able to encode");
+            server_transport
+                .send_to(packet.as_bytes(), client_addr)
+                .expect("should be able to send");
+        }
+        let Ok(ConnectionAttempt::Pending(_attempt)) = attempt.poll() else {
+            panic!("should still be connecting");
+        };
+
+        let mut cipher = opened.server_cipher(&nonce, &server_nonce);
+        let mut answered = Vec::new();
+        while let Ok((len, _)) = server_transport.recv_from(&mut buf) {
+            let body = match cipher.open(&mut buf[..len]) {
+                Some(packet) => PacketBody::decode(packet),
+                None => PacketBody::decode(&buf[..len]),
+            };
+            if let Ok(PacketBody::ChallengeResponse { token }) = body {
+                answered.push(token);
+            }
+        }
+        assert_eq!(
+            answered,
+            vec![2],
+            "should only answer the challenge of the server, with its keys"
+        );
+    }
+
+    /// Secure clients only trust unencrypted rejections once the handshake
+    /// times out.
+    fn short_handshake() -> ConnectionConfig {
+        ConnectionConfig::default().with_handshake_timeout(Duration::from_millis(100))
+    }
+
+    #[test]
+    fn single_use_token() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(42, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let mut server = create_server()
+            .with_private_key(private_key)
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+
+        let client = create_client()
+            .with_connection_config(short_handshake())
+            .with_connect_token(token);
+        let (client, _) =
+            try_connect_with(&mut server, client).expect("should connect with a valid token");
+
+        // Even from the same address
+        let client_addr = client.addr();
+        let mut attempt = client
+            .disconnect()
+            .connect(client_addr, server.addr())
+            .expect("should be able to start connecting");
+        let result = loop {
+            server.poll();
+            match attempt.poll() {
+                Ok(ConnectionAttempt::Pending(pending)) => attempt = pending,
+                Ok(ConnectionAttempt::Connected(_)) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::Rejected(RejectReason::InvalidConnectToken))
+            ),
+            "should not accept a token twice"
+        );
+    }
+
+    #[test]
+    fn send_large_message() {
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let (mut client, id) = connect(&mut server);
+
+        let snapshot: Vec<u8> = (0..50_000).map(|i| (i % 251) as u8).collect();
+        server
+            .send(id, DefaultChannel::Reliable, &snapshot)
+            .expect("should be able to send a large message");
+
+        let mut received = None;
+        for _ in 0..100 {
+            server.poll();
+            server.flush();
+            if let Some(ClientEvent::Received(_, bytes)) = client.poll().pop() {
+                received = Some(bytes);
+                break;
+            }
+            client.flush().expect("should be able to flush");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            received,
+            Some(snapshot),
+            "should receive the whole reassembled message"
+        );
+    }
+
+    #[test]
+    fn reliable_under_bad_conditions() {
+        let conditions = LinkConditionerConfig::default()
+            .with_latency(Duration::from_millis(10))
+            .with_jitter(Duration::from_millis(5))
+            .with_loss(0.2)
+            .with_duplicate(0.1)
+            .with_reorder(0.1)
+            .with_seed(42);
+        let mut server = create_server()
+            .with_link_conditioner(conditions.clone().with_seed(43))
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let attempt = create_client()
+            .with_link_conditioner(conditions)
+            .connect(LOCAL_ADDR, server.addr())
+            .expect("should be able to start connecting");
+
+        let mut id = None;
+        let mut attempt = ConnectionAttempt::Pending(attempt);
+        while let ConnectionAttempt::Pending(pending) = attempt {
+            for event in server.poll() {
+                if let ServerEvent::Connected(connected) = event {
+                    id = Some(connected);
+                }
+            }
+            attempt = pending.poll().expect("should connect despite the losses");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let ConnectionAttempt::Connected(mut client) = attempt else {
+            unreachable!();
+        };
+
+        let sent: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; 100]).collect();
+        for message in &sent {
+            client
+                .send(DefaultChannel::Reliable, message)
+                .expect("should be able to send");
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            client.flush().expect("should be able to flush");
+            client.poll();
+            for event in server.poll() {
+                match event {
+                    ServerEvent::Connected(connected) => id = Some(connected),
+                    ServerEvent::Received(from, _, bytes) => {
+                        assert_eq!(Some(from), id, "should come from the client");
+                        received.push(bytes);
+                    }
+                    ServerEvent::Disconnected(..) => panic!("client should stay connected"),
+                }
+            }
+            server.flush();
+            if received.len() == sent.len() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(
+            received, sent,
+            "should receive every message once and in order"
+        );
+        assert!(
+            client.stats().resent_messages > 0,
+            "should have resent lost messages"
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let config = ConnectionConfig::default().with_timeout(Duration::from_millis(50));
+        let mut server = create_server()
+            .with_connection_config(config)
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let (_client, id) = connect(&mut server);
+
+        // Only the server is polled, so the client never answers
+        let mut server_events = Vec::new();
+        for _ in 0..20 {
+            server_events.extend(server.poll());
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::Disconnected(id, DisconnectReason::TimedOut)],
+            "server should drop the silent client"
+        );
+
+        let client_config = ConnectionConfig::default().with_timeout(Duration::from_millis(50));
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let attempt = create_client()
+            .with_connection_config(client_config)
+            .connect(LOCAL_ADDR, server.addr())
+            .expect("should be able to start connecting");
+        let mut attempt = ConnectionAttempt::Pending(attempt);
+        while let ConnectionAttempt::Pending(pending) = attempt {
+            server.poll();
+            attempt = pending.poll().expect("should be able to connect");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let ConnectionAttempt::Connected(mut timed_out) = attempt else {
+            unreachable!();
+        };
+        drop(server);
+
+        let mut client_events = Vec::new();
+        for _ in 0..20 {
+            client_events.extend(timed_out.poll());
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            client_events,
+            vec![ClientEvent::Disconnected(DisconnectReason::TimedOut)],
+            "client should drop the silent server"
+        );
+        assert!(!timed_out.is_connected(), "should not be connected anymore");
+    }
+
+    #[test]
+    fn graceful_disconnect() {
+        let mut server = create_server()
+            .listen(LOCAL_ADDR)
+            .expect("should be able to listen");
+        let (leaving, leaving_id) = connect(&mut server);
+        let (mut staying, _) = connect(&mut server);
+
+        leaving.disconnect();
+        let mut server_events = Vec::new();
+        for _ in 0..10 {
+            server_events.extend(server.poll());
+            if !server_events.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::Disconnected(
+                leaving_id,
+                DisconnectReason::ClientLeft
+            )],
+            "server should report the client leaving"
+        );
+
+        server.stop();
+        let mut client_events = Vec::new();
+        for _ in 0..10 {
+            client_events.extend(staying.poll());
+            if !client_events.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            client_events,
+            vec![ClientEvent::Disconnected(DisconnectReason::ServerStopped)],
+            "client should report the server stopping"
+        );
+        assert!(
+            matches!(
+                staying.send(DefaultChannel::Reliable, b"bye"),
+                Err(ClientError::Disconnected(DisconnectReason::ServerStopped))
+            ),
+            "should not send after the connection ended"
+        );
+    }
 }
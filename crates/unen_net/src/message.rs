@@ -0,0 +1,284 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+use bincode::{Decode, Encode};
+use thiserror::Error;
+use unen_event::prelude::{Event, EventEmitter};
+
+use crate::{
+    protocol::encoding::{self, decode_from_vec},
+    server::ClientId,
+};
+
+/// Bytes of the message id prefixing every typed message.
+pub const MESSAGE_ID_SIZE: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("message {0} is not registered")]
+    UnregisteredMessage(MessageId),
+    #[error("message of {0} bytes is too short to carry an id")]
+    MissingId(usize),
+    #[error("encoding error: {0}")]
+    EncodingError(#[from] encoding::Error),
+}
+
+/// Identifier of a message type, the same on every build so that clients and
+/// servers agree on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId(u32);
+
+impl MessageId {
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:08x}", self.0)
+    }
+}
+
+/// A type sent over the network, usually implemented with
+/// `#[derive(NetMessage)]` which derives its id from the type name, or takes
+/// it from `#[net_message(id = ...)]`.
+pub trait NetMessage: Encode + Decode<()> + Send + Sync + 'static {
+    const ID: MessageId;
+}
+
+/// Event emitted by a client for every registered message received from the
+/// server.
+#[derive(Debug)]
+pub struct ServerMessage<M>(pub M);
+
+impl<M: NetMessage> Event for ServerMessage<M> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event emitted by a server for every registered message received from a
+/// client.
+#[derive(Debug)]
+pub struct ClientMessage<M> {
+    pub client: ClientId,
+    pub message: M,
+}
+
+impl<M: NetMessage> Event for ClientMessage<M> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Message of any type, carried by the events asking the network handlers to
+/// send it.
+pub(crate) trait AnyMessage: Send + Sync {
+    fn encode(&self, messages: &MessageRegistry) -> Result<Vec<u8>, Error>;
+}
+
+impl<M: NetMessage> AnyMessage for M {
+    fn encode(&self, messages: &MessageRegistry) -> Result<Vec<u8>, Error> {
+        messages.encode(self)
+    }
+}
+
+/// Decodes a message and emits it, with the client it came from on servers.
+type Dispatcher = fn(Option<ClientId>, &[u8], &EventEmitter) -> Result<(), encoding::Error>;
+
+/// Message types a client or server knows about, received messages of other
+/// types are rejected.
+///
+/// Both ends of a connection must register the messages they exchange.
+#[derive(Debug, Clone, Default)]
+pub struct MessageRegistry {
+    dispatchers: HashMap<MessageId, (TypeId, Dispatcher)>,
+}
+
+impl MessageRegistry {
+    /// Registers a message type.
+    ///
+    /// # Panics
+    ///
+    /// Panics when another type was registered with the same id.
+    pub fn with_message<M: NetMessage>(mut self) -> Self {
+        let previous = self
+            .dispatchers
+            .insert(M::ID, (TypeId::of::<M>(), dispatch::<M>));
+        assert!(
+            previous.is_none_or(|(type_id, _)| type_id == TypeId::of::<M>()),
+            "message {} of {} is already registered",
+            M::ID,
+            std::any::type_name::<M>()
+        );
+        self
+    }
+
+    pub fn contains(&self, id: MessageId) -> bool {
+        self.dispatchers.contains_key(&id)
+    }
+
+    /// Encodes a message prefixed with its id, ready to be sent on a channel.
+    pub fn encode<M: NetMessage>(&self, message: &M) -> Result<Vec<u8>, Error> {
+        if !self.contains(M::ID) {
+            return Err(Error::UnregisteredMessage(M::ID));
+        }
+
+        let mut data = M::ID.raw().to_le_bytes().to_vec();
+        bincode::encode_into_std_write(message, &mut data, bincode::config::standard())
+            .map_err(encoding::Error::from)?;
+        Ok(data)
+    }
+
+    /// Decodes a message received from the server and emits it as a
+    /// [`ServerMessage`].
+    pub fn dispatch_from_server(&self, data: &[u8], emitter: &EventEmitter) -> Result<(), Error> {
+        self.dispatch(None, data, emitter)
+    }
+
+    /// Decodes a message received from a client and emits it as a
+    /// [`ClientMessage`].
+    pub fn dispatch_from_client(
+        &self,
+        client: ClientId,
+        data: &[u8],
+        emitter: &EventEmitter,
+    ) -> Result<(), Error> {
+        self.dispatch(Some(client), data, emitter)
+    }
+
+    fn dispatch(
+        &self,
+        client: Option<ClientId>,
+        data: &[u8],
+        emitter: &EventEmitter,
+    ) -> Result<(), Error> {
+        let (id, payload) = data
+            .split_first_chunk::<MESSAGE_ID_SIZE>()
+            .ok_or(Error::MissingId(data.len()))?;
+        let id = MessageId::new(u32::from_le_bytes(*id));
+        let (_, dispatcher) = self
+            .dispatchers
+            .get(&id)
+            .ok_or(Error::UnregisteredMessage(id))?;
+
+        Ok(dispatcher(client, payload, emitter)?)
+    }
+}
+
+fn dispatch<M: NetMessage>(
+    client: Option<ClientId>,
+    payload: &[u8],
+    emitter: &EventEmitter,
+) -> Result<(), encoding::Error> {
+    let message = decode_from_vec::<M>(payload)?;
+    match client {
+        Some(client) => emitter.emit(ClientMessage { client, message }),
+        None => emitter.emit(ServerMessage(message)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use unen_event::prelude::{EventBox, EventHandler, EventManager};
+    use unen_net_derive::NetMessage;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode, NetMessage)]
+    struct Chat {
+        text: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode, NetMessage)]
+    #[net_message(id = 7)]
+    struct Input(u8);
+
+    /// Collects the chat messages the manager dispatches.
+    struct ChatLog(std::sync::mpsc::Sender<(Option<ClientId>, String)>);
+
+    impl EventHandler for ChatLog {
+        fn handle(&mut self, event: &EventBox) -> bool {
+            if let Some(ServerMessage(chat)) = event.downcast_ref::<ServerMessage<Chat>>() {
+                let _ = self.0.send((None, chat.text.clone()));
+            }
+            if let Some(ClientMessage { client, message }) =
+                event.downcast_ref::<ClientMessage<Chat>>()
+            {
+                let _ = self.0.send((Some(*client), message.text.clone()));
+            }
+            false
+        }
+    }
+
+    #[test]
+    fn test_message_ids() {
+        assert_eq!(Input::ID, MessageId::new(7), "should use the given id");
+        assert_ne!(Chat::ID, Input::ID, "should derive distinct ids");
+        assert_eq!(
+            Chat::ID,
+            MessageId::new(0x2279_d8cb),
+            "should derive the id from the type name only"
+        );
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let (sender, received) = std::sync::mpsc::channel();
+        let mut manager = EventManager::default();
+        manager.add_handler(ChatLog(sender));
+        let emitter = manager.get_emitter();
+
+        let registry = MessageRegistry::default().with_message::<Chat>();
+        let chat = Chat {
+            text: "hello".to_string(),
+        };
+        let data = registry.encode(&chat).expect("should encode");
+        registry
+            .dispatch_from_server(&data, &emitter)
+            .expect("should dispatch");
+        registry
+            .dispatch_from_client(ClientId::new(3), &data, &emitter)
+            .expect("should dispatch");
+        manager.step();
+
+        assert_eq!(
+            received.try_iter().collect::<Vec<_>>(),
+            vec![
+                (None, "hello".to_string()),
+                (Some(ClientId::new(3)), "hello".to_string())
+            ],
+            "should emit typed events"
+        );
+
+        assert!(
+            matches!(
+                registry.encode(&Input(1)),
+                Err(Error::UnregisteredMessage(id)) if id == Input::ID
+            ),
+            "should not encode unregistered messages"
+        );
+        let mut unknown = Input::ID.raw().to_le_bytes().to_vec();
+        unknown.push(1);
+        assert!(
+            matches!(
+                registry.dispatch_from_server(&unknown, &emitter),
+                Err(Error::UnregisteredMessage(_))
+            ),
+            "should reject unregistered ids"
+        );
+        assert!(
+            registry.dispatch_from_server(&[1, 2], &emitter).is_err(),
+            "should reject truncated messages"
+        );
+    }
+}
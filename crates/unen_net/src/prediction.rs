@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bincode::{Decode, Encode};
+
+use crate::message::{MessageId, NetMessage};
+
+/// Default amount of inputs sent in every input message, so that a few lost
+/// packets do not lose inputs.
+pub const DEFAULT_INPUT_REDUNDANCY: usize = 3;
+/// Default amount of inputs kept while the server did not process them.
+pub const DEFAULT_MAX_PENDING_INPUTS: usize = 128;
+
+/// State a client predicts by applying its own inputs before the server
+/// confirms them.
+pub trait Predict: Clone + PartialEq {
+    type Input: Clone;
+
+    /// Advances the state by one tick, the same way the server does.
+    fn step(&mut self, input: &Self::Input);
+}
+
+/// Latest inputs of a client along with their tick, oldest first.
+///
+/// One input type may be registered per [`MessageRegistry`], all of them
+/// share the same id.
+///
+/// [`MessageRegistry`]: crate::message::MessageRegistry
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct InputMessage<I> {
+    pub inputs: Vec<(u32, I)>,
+}
+
+impl<I: Encode + Decode<()> + Send + Sync + 'static> NetMessage for InputMessage<I> {
+    const ID: MessageId = MessageId::new(0x756e_0003);
+}
+
+/// Counters of a [`Predictor`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PredictionStats {
+    /// Authoritative states that differed from the prediction.
+    pub mispredictions: u64,
+    /// Inputs applied again after a misprediction.
+    pub replayed_inputs: u64,
+}
+
+/// Client side of prediction: applies inputs locally as soon as they are
+/// made, and rewinds to the authoritative state then replays the inputs the
+/// server did not process yet when a prediction was wrong.
+#[derive(Debug, Clone)]
+pub struct Predictor<S: Predict> {
+    state: S,
+    /// Inputs the server did not process yet, with the state predicted after
+    /// each of them.
+    pending: VecDeque<(u32, S::Input, S)>,
+    redundancy: usize,
+    max_pending: usize,
+    /// Tick of the newest input the server processed.
+    acked: Option<u32>,
+    stats: PredictionStats,
+}
+
+impl<S: Predict> Predictor<S> {
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            pending: VecDeque::new(),
+            redundancy: DEFAULT_INPUT_REDUNDANCY,
+            max_pending: DEFAULT_MAX_PENDING_INPUTS,
+            acked: None,
+            stats: PredictionStats::default(),
+        }
+    }
+
+    /// Sets how many of the latest inputs every input message carries.
+    pub fn with_redundancy(mut self, redundancy: usize) -> Self {
+        self.redundancy = redundancy.max(1);
+        self
+    }
+
+    /// Sets how many inputs are kept while the server did not process them,
+    /// the oldest ones can not be replayed anymore past that.
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Applies the input of a tick to the predicted state, returning the
+    /// message to send to the server, typically on an unreliable channel.
+    pub fn predict(&mut self, tick: u32, input: S::Input) -> InputMessage<S::Input> {
+        self.state.step(&input);
+        self.pending.push_back((tick, input, self.state.clone()));
+        while self.pending.len() > self.max_pending {
+            self.pending.pop_front();
+        }
+
+        let skip = self.pending.len().saturating_sub(self.redundancy);
+        InputMessage {
+            inputs: self
+                .pending
+                .iter()
+                .skip(skip)
+                .map(|(tick, input, _)| (*tick, input.clone()))
+                .collect(),
+        }
+    }
+
+    /// Handles the authoritative state after the server processed the input
+    /// of `tick`, replaying the later inputs on it when the prediction was
+    /// wrong.
+    pub fn reconcile(&mut self, tick: u32, authoritative: S) {
+        if self.acked.is_some_and(|acked| acked >= tick) {
+            return;
+        }
+        self.acked = Some(tick);
+
+        let mut predicted = None;
+        while let Some((pending, ..)) = self.pending.front() {
+            if *pending > tick {
+                break;
+            }
+            if let Some((pending, _, state)) = self.pending.pop_front() {
+                predicted = (pending == tick).then_some(state);
+            }
+        }
+        if predicted.as_ref() == Some(&authoritative) {
+            return;
+        }
+
+        self.stats.mispredictions += 1;
+        self.state = authoritative;
+        for (_, input, predicted) in &mut self.pending {
+            self.state.step(input);
+            *predicted = self.state.clone();
+            self.stats.replayed_inputs += 1;
+        }
+    }
+
+    /// Predicted state, including every input made so far.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Amount of inputs the server did not process yet.
+    pub fn pending_inputs(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn stats(&self) -> PredictionStats {
+        self.stats
+    }
+}
+
+/// Server side of prediction: collects the inputs of a client, received
+/// several times and out of order, so they are processed once and in order.
+#[derive(Debug, Clone)]
+pub struct InputBuffer<I> {
+    inputs: BTreeMap<u32, I>,
+    capacity: usize,
+    last_processed: Option<u32>,
+}
+
+impl<I: Clone> InputBuffer<I> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inputs: BTreeMap::new(),
+            capacity,
+            last_processed: None,
+        }
+    }
+
+    /// Buffers the inputs of a message that were not processed yet.
+    pub fn receive(&mut self, message: &InputMessage<I>) {
+        for (tick, input) in &message.inputs {
+            if self.last_processed.is_none_or(|last| *tick > last) {
+                self.inputs.entry(*tick).or_insert_with(|| input.clone());
+            }
+        }
+        while self.inputs.len() > self.capacity {
+            self.inputs.pop_first();
+        }
+    }
+
+    /// Takes the oldest input not processed yet.
+    pub fn next_input(&mut self) -> Option<(u32, I)> {
+        let (tick, input) = self.inputs.pop_first()?;
+        self.last_processed = Some(tick);
+        Some((tick, input))
+    }
+
+    /// Tick of the last input processed, to send along with the
+    /// authoritative state for [`Predictor::reconcile`].
+    pub fn last_processed(&self) -> Option<u32> {
+        self.last_processed
+    }
+
+    /// Amount of inputs waiting to be processed.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+impl<I: Clone> Default for InputBuffer<I> {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PENDING_INPUTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position on a line, the server stops it at a wall the client does
+    /// not know about.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Position(i32);
+
+    impl Predict for Position {
+        type Input = i32;
+
+        fn step(&mut self, input: &i32) {
+            self.0 += input;
+        }
+    }
+
+    #[test]
+    fn test_prediction_and_reconciliation() {
+        let mut client = Predictor::new(Position(0));
+        let mut server = Position(0);
+        let mut inputs = InputBuffer::default();
+
+        for tick in 0..4 {
+            let message = client.predict(tick, 1);
+            assert!(
+                message.inputs.len() <= DEFAULT_INPUT_REDUNDANCY,
+                "should send the latest inputs"
+            );
+            // The message of tick 1 is lost
+            if tick != 1 {
+                inputs.receive(&message);
+            }
+        }
+        assert_eq!(client.state(), &Position(4), "should predict right away");
+
+        while let Some((_, input)) = inputs.next_input() {
+            server.step(&input);
+            if inputs.last_processed() == Some(1) {
+                client.reconcile(1, server.clone());
+            }
+        }
+        assert_eq!(
+            inputs.last_processed(),
+            Some(3),
+            "should recover lost inputs from the next messages"
+        );
+        assert_eq!(client.stats().mispredictions, 0, "should predict right");
+        assert_eq!(client.pending_inputs(), 2, "should drop processed inputs");
+
+        // The server hits a wall at 3 while the client keeps moving
+        client.predict(4, 1);
+        client.predict(5, 1);
+        client.reconcile(3, Position(3));
+        assert_eq!(
+            client.state(),
+            &Position(5),
+            "should replay the inputs on the authoritative state"
+        );
+        assert_eq!(
+            client.stats(),
+            PredictionStats {
+                mispredictions: 1,
+                replayed_inputs: 2,
+            },
+            "should count mispredictions"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    connection::DisconnectReason,
+    protocol::{
+        handshake::RejectReason,
+        packet::{self, Compression, Packet, PacketHeader, PacketKind, PROTOCOL_ID},
+        reliability::{AckHeader, Message},
+        security::{ChallengeTag, HandshakeNonce, SealedToken},
+    },
+};
+
+/// Content of every datagram exchanged between client and server.
+///
+/// On the wire, the kind of body and the acknowledgements of data packets
+/// are part of the [`PacketHeader`], only the rest is in the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketBody {
+    /// Sent by the client to start the handshake, along with its connect
+    /// token when the server is secure.
+    ConnectRequest {
+        protocol_id: [u8; 8],
+        version: u16,
+        token: Option<SealedToken>,
+        nonce: HandshakeNonce,
+    },
+    /// Sent by the server, the client must echo the token back. Secure
+    /// clients derive the keys of the connection from both nonces, once the
+    /// tag proves the challenge comes from the server.
+    Challenge {
+        token: u64,
+        nonce: HandshakeNonce,
+        tag: Option<ChallengeTag>,
+    },
+    /// Sent by the client to prove it owns its address.
+    ChallengeResponse { token: u64 },
+    /// Sent by the server once the client is connected.
+    Accepted { version: u16 },
+    /// Sent by the server when the connection is refused.
+    Rejected(RejectReason),
+    /// Application messages along with the acknowledgement of received
+    /// packets, carries no message at all when only acknowledging or keeping
+    /// the connection alive.
+    Data {
+        header: AckHeader,
+        messages: Vec<Message>,
+    },
+    /// Sent by either side to end the connection gracefully.
+    Disconnect(DisconnectReason),
+}
+
+impl PacketBody {
+    pub fn connect_request(
+        version: u16,
+        token: Option<SealedToken>,
+        nonce: HandshakeNonce,
+    ) -> Self {
+        PacketBody::ConnectRequest {
+            protocol_id: *PROTOCOL_ID,
+            version,
+            token,
+            nonce,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Packet, packet::Error> {
+        let mut data = Vec::new();
+        self.encode_into(Compression::None, &mut data)?;
+        Ok(Packet::from_bytes(data))
+    }
+
+    /// Encodes the body into a reusable buffer, see [`packet::encode_into`].
+    pub fn encode_into(
+        &self,
+        compression: Compression,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), packet::Error> {
+        match self {
+            PacketBody::Data { header, messages } if messages.is_empty() => packet::encode_into(
+                PacketHeader::new(PacketKind::Heartbeat).with_ack(*header),
+                (),
+                compression,
+                buf,
+            ),
+            PacketBody::Data { header, messages } => packet::encode_into(
+                PacketHeader::new(PacketKind::Data).with_ack(*header),
+                messages,
+                compression,
+                buf,
+            ),
+            PacketBody::Disconnect(reason) => packet::encode_into(
+                PacketHeader::new(PacketKind::Disconnect),
+                reason,
+                compression,
+                buf,
+            ),
+            _ => packet::encode_into(
+                PacketHeader::new(PacketKind::Connect),
+                Handshake::from_body(self),
+                compression,
+                buf,
+            ),
+        }
+    }
+
+    /// Decodes a received datagram according to the kind of its header.
+    pub fn decode(data: &[u8]) -> Result<PacketBody, packet::Error> {
+        let body = match packet::decode_header(data)?.kind {
+            PacketKind::Connect => packet::decode_from::<Handshake>(data)?.1.into_body(),
+            PacketKind::Data => {
+                let (header, messages) = packet::decode_from(data)?;
+                PacketBody::Data {
+                    header: header.ack_header(),
+                    messages,
+                }
+            }
+            PacketKind::Heartbeat => {
+                let (header, ()) = packet::decode_from(data)?;
+                PacketBody::Data {
+                    header: header.ack_header(),
+                    messages: Vec::new(),
+                }
+            }
+            PacketKind::Disconnect => PacketBody::Disconnect(packet::decode_from(data)?.1),
+        };
+        Ok(body)
+    }
+
+    pub fn from_packet(packet: &Packet) -> Result<PacketBody, packet::Error> {
+        PacketBody::decode(packet.as_bytes())
+    }
+}
+
+/// Payload of [`PacketKind::Connect`] packets.
+#[derive(Debug, Clone, Encode, Decode)]
+enum Handshake {
+    ConnectRequest {
+        protocol_id: [u8; 8],
+        version: u16,
+        token: Option<SealedToken>,
+        nonce: HandshakeNonce,
+    },
+    Challenge {
+        token: u64,
+        nonce: HandshakeNonce,
+        tag: Option<ChallengeTag>,
+    },
+    ChallengeResponse {
+        token: u64,
+    },
+    Accepted {
+        version: u16,
+    },
+    Rejected(RejectReason),
+}
+
+impl Handshake {
+    /// Only called with handshake bodies, others have their own kind.
+    fn from_body(body: &PacketBody) -> Self {
+        match body.clone() {
+            PacketBody::ConnectRequest {
+                protocol_id,
+                version,
+                token,
+                nonce,
+            } => Handshake::ConnectRequest {
+                protocol_id,
+                version,
+                token,
+                nonce,
+            },
+            PacketBody::Challenge { token, nonce, tag } => {
+                Handshake::Challenge { token, nonce, tag }
+            }
+            PacketBody::ChallengeResponse { token } => Handshake::ChallengeResponse { token },
+            PacketBody::Accepted { version } => Handshake::Accepted { version },
+            PacketBody::Rejected(reason) => Handshake::Rejected(reason),
+            PacketBody::Data { .. } | PacketBody::Disconnect(_) => {
+                unreachable!("not a handshake body")
+            }
+        }
+    }
+
+    fn into_body(self) -> PacketBody {
+        match self {
+            Handshake::ConnectRequest {
+                protocol_id,
+                version,
+                token,
+                nonce,
+            } => PacketBody::ConnectRequest {
+                protocol_id,
+                version,
+                token,
+                nonce,
+            },
+            Handshake::Challenge { token, nonce, tag } => {
+                PacketBody::Challenge { token, nonce, tag }
+            }
+            Handshake::ChallengeResponse { token } => PacketBody::ChallengeResponse { token },
+            Handshake::Accepted { version } => PacketBody::Accepted { version },
+            Handshake::Rejected(reason) => PacketBody::Rejected(reason),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::protocol::{packet::PACKET_MAX_SIZE, security::ENCRYPTION_OVERHEAD};
+
+/// Largest datagram exchanged, an encrypted packet.
+pub const DATAGRAM_MAX_SIZE: usize = PACKET_MAX_SIZE + ENCRYPTION_OVERHEAD;
+/// Default amount of idle buffers kept by a pool.
+pub const DEFAULT_POOL_CAPACITY: usize = 64;
+
+/// Recycles datagram buffers, so that encoding, sending and receiving
+/// packets does not allocate once warmed up.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    free: Vec<Vec<u8>>,
+    capacity: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool keeping at most `capacity` idle buffers.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Takes an empty buffer able to hold any datagram.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.free
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(DATAGRAM_MAX_SIZE))
+    }
+
+    /// Hands a buffer back for reuse, it is dropped when the pool is full.
+    pub fn give(&mut self, mut buf: Vec<u8>) {
+        if self.free.len() < self.capacity {
+            buf.clear();
+            self.free.push(buf);
+        }
+    }
+
+    /// Amount of idle buffers.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let mut pool = BufferPool::new(1);
+        let mut buf = pool.take();
+        buf.extend_from_slice(b"packet");
+        let ptr = buf.as_ptr();
+        pool.give(buf);
+        pool.give(Vec::new());
+        assert_eq!(pool.len(), 1, "should keep at most its capacity");
+
+        let buf = pool.take();
+        assert!(buf.is_empty(), "should hand out empty buffers");
+        assert_eq!(buf.as_ptr(), ptr, "should reuse the allocation");
+        assert!(
+            buf.capacity() >= DATAGRAM_MAX_SIZE,
+            "should fit any datagram"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::protocol::{
+    fragment::{self, MESSAGE_MAX_SIZE},
+    packet::Compression,
+    reliability::{sequence_greater_than, Message, FRAGMENT_SIZE},
+};
+
+/// How many reliable messages of a channel may be in flight before newer ones
+/// wait for older ones to be acknowledged.
+pub const RELIABLE_WINDOW: u16 = 256;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("channel {0} is not configured")]
+    UnknownChannel(ChannelId),
+    #[error("message of {0} bytes is too large")]
+    MessageTooLarge(usize),
+}
+
+/// Identifier of a channel, game code usually converts its own channel enum
+/// into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelId(u8);
+
+impl ChannelId {
+    pub const fn new(id: u8) -> Self {
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Channels every connection is configured with unless overridden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefaultChannel {
+    /// Reliable ordered channel with id 0.
+    Reliable,
+    /// Unreliable channel with id 1.
+    Unreliable,
+}
+
+impl From<DefaultChannel> for ChannelId {
+    fn from(channel: DefaultChannel) -> Self {
+        match channel {
+            DefaultChannel::Reliable => ChannelId(0),
+            DefaultChannel::Unreliable => ChannelId(1),
+        }
+    }
+}
+
+/// Delivery guarantee of the messages sent on a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    /// Delivered at most once, possibly out of order, lost messages are not
+    /// resent.
+    Unreliable,
+    /// Like [`ChannelKind::Unreliable`], but messages older than the last
+    /// delivered one are dropped. Meant for state updates superseded by the
+    /// next one.
+    UnreliableSequenced,
+    /// Delivered exactly once in any order, lost messages are resent until
+    /// acknowledged.
+    ReliableUnordered,
+    /// Delivered exactly once and in the order they were sent.
+    ReliableOrdered,
+}
+
+impl ChannelKind {
+    pub fn is_reliable(&self) -> bool {
+        matches!(
+            self,
+            ChannelKind::ReliableUnordered | ChannelKind::ReliableOrdered
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    kind: ChannelKind,
+    priority: u8,
+    compression: Compression,
+}
+
+impl ChannelConfig {
+    pub fn new(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            priority: 0,
+            compression: Compression::None,
+        }
+    }
+
+    /// Sets the priority of the channel, messages of channels with a higher
+    /// priority are written first when not everything fits in a flush.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the compression of the packets carrying messages of the
+    /// channel, packets are only sent compressed when it makes them smaller.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn kind(&self) -> ChannelKind {
+        self.kind
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+/// Reliable message waiting for its acknowledgement.
+#[derive(Debug, Clone)]
+struct PendingMessage {
+    message: Message,
+    sent_at: Option<Instant>,
+    acked: bool,
+}
+
+/// Send queue of a channel.
+#[derive(Debug, Clone)]
+pub(crate) struct SendChannel {
+    id: ChannelId,
+    config: ChannelConfig,
+    next_id: u16,
+    /// Reliable messages not acknowledged yet.
+    pending: VecDeque<PendingMessage>,
+    /// Unreliable messages not written yet.
+    queue: VecDeque<Message>,
+    resent: u64,
+}
+
+impl SendChannel {
+    pub fn new(id: ChannelId, config: ChannelConfig) -> Self {
+        Self {
+            id,
+            config,
+            next_id: 0,
+            pending: VecDeque::new(),
+            queue: VecDeque::new(),
+            resent: 0,
+        }
+    }
+
+    pub fn id(&self) -> ChannelId {
+        self.id
+    }
+
+    pub fn config(&self) -> &ChannelConfig {
+        &self.config
+    }
+
+    /// Queues a message, split into fragments when it does not fit a packet.
+    pub fn push(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MESSAGE_MAX_SIZE {
+            return Err(Error::MessageTooLarge(data.len()));
+        }
+
+        if data.len() <= FRAGMENT_SIZE {
+            self.push_message(None, data);
+        } else {
+            for (header, chunk) in fragment::split(data) {
+                self.push_message(Some(header), chunk);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands the messages due for sending to `write` until it gives one
+    /// back: reliable messages never sent or not acknowledged within
+    /// `resend_interval`, then queued unreliable messages.
+    pub fn write(
+        &mut self,
+        now: Instant,
+        resend_interval: Duration,
+        mut write: impl FnMut(Message) -> Result<(), Message>,
+    ) {
+        for pending in self.pending.iter_mut().take(RELIABLE_WINDOW as usize) {
+            let due = pending
+                .sent_at
+                .is_none_or(|sent_at| now.duration_since(sent_at) >= resend_interval);
+            if pending.acked || !due {
+                continue;
+            }
+
+            if write(pending.message.clone()).is_err() {
+                return;
+            }
+            if pending.sent_at.is_some() {
+                self.resent += 1;
+            }
+            pending.sent_at = Some(now);
+        }
+
+        while let Some(message) = self.queue.pop_front() {
+            if let Err(message) = write(message) {
+                self.queue.push_front(message);
+                return;
+            }
+        }
+    }
+
+    pub fn ack(&mut self, id: u16) {
+        let Some(oldest) = self.pending.front().map(|pending| pending.message.id) else {
+            return;
+        };
+        if let Some(pending) = self.pending.get_mut(id.wrapping_sub(oldest) as usize) {
+            pending.acked = true;
+        }
+        while self.pending.front().is_some_and(|pending| pending.acked) {
+            self.pending.pop_front();
+        }
+    }
+
+    /// Amount of reliable messages not acknowledged yet.
+    pub fn unacked_count(&self) -> usize {
+        self.pending.iter().filter(|pending| !pending.acked).count()
+    }
+
+    /// Amount of reliable messages sent again.
+    pub fn resent_count(&self) -> u64 {
+        self.resent
+    }
+
+    fn push_message(&mut self, fragment: Option<fragment::FragmentHeader>, data: &[u8]) {
+        let message = Message {
+            channel: self.id.raw(),
+            id: self.next_id,
+            fragment,
+            data: data.to_vec(),
+        };
+        self.next_id = self.next_id.wrapping_add(1);
+
+        if self.config.kind.is_reliable() {
+            self.pending.push_back(PendingMessage {
+                message,
+                sent_at: None,
+                acked: false,
+            });
+        } else {
+            self.queue.push_back(message);
+        }
+    }
+}
+
+/// Receiving side of a channel, enforcing its delivery guarantee.
+#[derive(Debug, Clone)]
+pub(crate) struct ReceiveChannel {
+    kind: ChannelKind,
+    /// Lowest reliable message id not received yet.
+    receive_id: u16,
+    /// Reliable ordered messages received ahead of `receive_id`.
+    out_of_order: HashMap<u16, Message>,
+    /// Reliable unordered message ids received ahead of `receive_id`.
+    received: HashSet<u16>,
+    /// Id of the last unreliable sequenced message delivered.
+    last_sequence: Option<u16>,
+}
+
+impl ReceiveChannel {
+    pub fn new(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            receive_id: 0,
+            out_of_order: HashMap::new(),
+            received: HashSet::new(),
+            last_sequence: None,
+        }
+    }
+
+    /// Lowest reliable message id not received yet, none for unreliable
+    /// channels.
+    pub fn receive_id(&self) -> Option<u16> {
+        self.kind.is_reliable().then_some(self.receive_id)
+    }
+
+    /// Handles a received message, passing what can be delivered to
+    /// `deliver`.
+    pub fn receive(&mut self, message: Message, mut deliver: impl FnMut(Message)) {
+        let id = message.id;
+        match self.kind {
+            ChannelKind::Unreliable => deliver(message),
+            ChannelKind::UnreliableSequenced => {
+                // Fragments of a message are sequenced as one, by their first
+                // id, so that they may arrive in any order
+                let sequence = message
+                    .fragment
+                    .map_or(id, |fragment| id.wrapping_sub(fragment.index));
+                let newest = self.last_sequence.is_none_or(|last| {
+                    sequence_greater_than(sequence, last)
+                        || (message.fragment.is_some() && sequence == last)
+                });
+                if newest {
+                    self.last_sequence = Some(sequence);
+                    deliver(message);
+                }
+            }
+            ChannelKind::ReliableUnordered => {
+                if self.is_stale(id) || !self.received.insert(id) {
+                    return;
+                }
+                deliver(message);
+                while self.received.remove(&self.receive_id) {
+                    self.receive_id = self.receive_id.wrapping_add(1);
+                }
+            }
+            ChannelKind::ReliableOrdered => {
+                if self.is_stale(id) {
+                    return;
+                }
+                self.out_of_order.entry(id).or_insert(message);
+                while let Some(message) = self.out_of_order.remove(&self.receive_id) {
+                    deliver(message);
+                    self.receive_id = self.receive_id.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Whether a reliable message id is behind the window, meaning it was
+    /// already delivered.
+    fn is_stale(&self, id: u16) -> bool {
+        id.wrapping_sub(self.receive_id) >= RELIABLE_WINDOW
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive_all(kind: ChannelKind, ids: &[u16]) -> Vec<u16> {
+        let mut channel = ReceiveChannel::new(kind);
+        let mut delivered = Vec::new();
+        for &id in ids {
+            let message = Message {
+                channel: 0,
+                id,
+                fragment: None,
+                data: Vec::new(),
+            };
+            channel.receive(message, |message| delivered.push(message.id));
+        }
+        delivered
+    }
+
+    #[test]
+    fn test_receive_guarantees() {
+        let ids = [1, 0, 0, 3, 2];
+        assert_eq!(
+            receive_all(ChannelKind::Unreliable, &ids),
+            vec![1, 0, 0, 3, 2],
+            "unreliable should deliver everything as received"
+        );
+        assert_eq!(
+            receive_all(ChannelKind::UnreliableSequenced, &ids),
+            vec![1, 3],
+            "unreliable sequenced should drop stale messages"
+        );
+        assert_eq!(
+            receive_all(ChannelKind::ReliableUnordered, &ids),
+            vec![1, 0, 3, 2],
+            "reliable unordered should drop duplicates"
+        );
+        assert_eq!(
+            receive_all(ChannelKind::ReliableOrdered, &ids),
+            vec![0, 1, 2, 3],
+            "reliable ordered should deliver in order"
+        );
+    }
+
+    #[test]
+    fn test_resend_after_interval() {
+        let config = ChannelConfig::new(ChannelKind::ReliableOrdered);
+        let mut channel = SendChannel::new(ChannelId::new(0), config);
+        channel.push(b"hello").expect("should be able to queue");
+
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+        let count = |channel: &mut SendChannel, now| {
+            let mut count = 0;
+            channel.write(now, interval, |_| {
+                count += 1;
+                Ok(())
+            });
+            count
+        };
+
+        assert_eq!(count(&mut channel, start), 1, "should send a new message");
+        assert_eq!(
+            count(&mut channel, start + interval / 2),
+            0,
+            "should wait before resending"
+        );
+        assert_eq!(
+            count(&mut channel, start + interval),
+            1,
+            "should resend after the interval"
+        );
+        channel.ack(0);
+        assert_eq!(
+            count(&mut channel, start + interval * 2),
+            0,
+            "should not resend acknowledged messages"
+        );
+    }
+}
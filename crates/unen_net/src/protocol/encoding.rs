@@ -1,6 +1,12 @@
 use bincode::{Decode, Encode};
 use thiserror::Error;
 
+use crate::protocol::fragment::MESSAGE_MAX_SIZE;
+
+/// Most memory decoding a message may claim, see
+/// [`PACKET_DECODE_LIMIT`](crate::protocol::packet::PACKET_DECODE_LIMIT).
+pub const MESSAGE_DECODE_LIMIT: usize = MESSAGE_MAX_SIZE * 16;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("encode error: {0}")]
@@ -13,6 +19,9 @@ pub fn encode_to_vec<T: Encode>(val: &T) -> Result<Vec<u8>, Error> {
     Ok(bincode::encode_to_vec(val, bincode::config::standard())?)
 }
 
+/// Decodes a value received from a peer, refusing length prefixes larger
+/// than a message can carry.
 pub fn decode_from_vec<T: Decode<()>>(src: &[u8]) -> Result<T, Error> {
-    Ok(bincode::decode_from_slice(src, bincode::config::standard())?.0)
+    let config = bincode::config::standard().with_limit::<{ MESSAGE_DECODE_LIMIT }>();
+    Ok(bincode::decode_from_slice(src, config)?.0)
 }
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bincode::{Decode, Encode};
+
+use crate::protocol::reliability::FRAGMENT_SIZE;
+
+/// How many fragments a message may be split into.
+pub const MAX_FRAGMENT_COUNT: usize = 512;
+/// Largest message accepted on a channel.
+pub const MESSAGE_MAX_SIZE: usize = FRAGMENT_SIZE * MAX_FRAGMENT_COUNT;
+/// Default amount of incomplete messages kept per peer.
+pub const DEFAULT_MAX_REASSEMBLY_BUFFERS: usize = 16;
+/// Default time after which an incomplete message is discarded.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Position of a fragment in the message it is part of.
+///
+/// Fragments of a message use consecutive message ids, so the first fragment
+/// id identifies the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct FragmentHeader {
+    pub index: u16,
+    pub count: u16,
+}
+
+/// Splits a message too large for a packet into fragments.
+pub fn split(data: &[u8]) -> impl Iterator<Item = (FragmentHeader, &[u8])> {
+    let count = data.len().div_ceil(FRAGMENT_SIZE) as u16;
+    data.chunks(FRAGMENT_SIZE)
+        .enumerate()
+        .map(move |(index, chunk)| {
+            (
+                FragmentHeader {
+                    index: index as u16,
+                    count,
+                },
+                chunk,
+            )
+        })
+}
+
+/// Fragments received so far for a message.
+#[derive(Debug, Clone)]
+struct ReassemblyBuffer {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    started_at: Instant,
+}
+
+/// Rebuilds fragmented messages on the receiving side.
+///
+/// Incomplete messages are capped per peer and discarded after a timeout, so
+/// that fragments never completing a message can not pile up.
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    max_buffers: usize,
+    timeout: Duration,
+    /// Keyed by channel and first fragment id.
+    buffers: HashMap<(u8, u16), ReassemblyBuffer>,
+}
+
+impl Reassembler {
+    pub fn new(max_buffers: usize, timeout: Duration) -> Self {
+        Self {
+            max_buffers,
+            timeout,
+            buffers: HashMap::new(),
+        }
+    }
+
+    /// Stores a fragment, returning the whole message once its last fragment
+    /// arrived.
+    pub fn receive(
+        &mut self,
+        channel: u8,
+        id: u16,
+        header: FragmentHeader,
+        data: Vec<u8>,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let count = header.count as usize;
+        if header.index >= header.count || count > MAX_FRAGMENT_COUNT || data.len() > FRAGMENT_SIZE
+        {
+            return None;
+        }
+
+        let key = (channel, id.wrapping_sub(header.index));
+        if !self.buffers.contains_key(&key) && self.buffers.len() >= self.max_buffers {
+            return None;
+        }
+        let buffer = self.buffers.entry(key).or_insert_with(|| ReassemblyBuffer {
+            fragments: vec![None; count],
+            missing: count,
+            started_at: now,
+        });
+        if buffer.fragments.len() != count {
+            return None;
+        }
+
+        let fragment = &mut buffer.fragments[header.index as usize];
+        if fragment.is_none() {
+            *fragment = Some(data);
+            buffer.missing -= 1;
+        }
+        if buffer.missing > 0 {
+            return None;
+        }
+
+        let buffer = self.buffers.remove(&key)?;
+        Some(buffer.fragments.into_iter().flatten().flatten().collect())
+    }
+
+    /// Discards incomplete messages older than the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        self.buffers
+            .retain(|_, buffer| now.duration_since(buffer.started_at) < self.timeout);
+    }
+
+    /// Discards the incomplete messages of a reliable channel whose ids were
+    /// all delivered already, `receive_id` being the lowest one not received
+    /// yet. Their missing fragments will not come anymore.
+    pub fn discard_delivered(&mut self, channel: u8, receive_id: u16) {
+        self.buffers.retain(|&(buffer_channel, first), buffer| {
+            let behind = receive_id.wrapping_sub(first) as usize;
+            buffer_channel != channel
+                || behind < buffer.fragments.len()
+                || behind >= u16::MAX as usize / 2
+        });
+    }
+
+    /// Amount of incomplete messages.
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let data: Vec<u8> = (0..FRAGMENT_SIZE * 3 + 10).map(|i| i as u8).collect();
+        let fragments: Vec<_> = split(&data).collect();
+        assert_eq!(fragments.len(), 4, "should split into 4 fragments");
+
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(1, DEFAULT_REASSEMBLY_TIMEOUT);
+        let mut message = None;
+        for (i, (header, chunk)) in fragments.into_iter().enumerate().rev() {
+            message = reassembler.receive(0, 10 + i as u16, header, chunk.to_vec(), now);
+        }
+        assert_eq!(message, Some(data), "should rebuild the message");
+        assert!(reassembler.is_empty(), "should free the buffer");
+    }
+
+    #[test]
+    fn test_cap_and_timeout() {
+        let header = FragmentHeader { index: 0, count: 2 };
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(1, Duration::from_secs(1));
+
+        reassembler.receive(0, 0, header, vec![1], now);
+        reassembler.receive(1, 2, header, vec![2], now);
+        assert_eq!(reassembler.len(), 1, "should cap buffers");
+
+        reassembler.expire(now + Duration::from_secs(1));
+        assert!(reassembler.is_empty(), "should discard incomplete messages");
+
+        reassembler.receive(0, 10, header, vec![1], now);
+        reassembler.discard_delivered(0, 11);
+        assert_eq!(reassembler.len(), 1, "should keep messages in delivery");
+        reassembler.discard_delivered(1, 12);
+        assert_eq!(reassembler.len(), 1, "should keep other channels");
+        reassembler.discard_delivered(0, 12);
+        assert!(reassembler.is_empty(), "should discard delivered messages");
+    }
+}
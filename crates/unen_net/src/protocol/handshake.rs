@@ -0,0 +1,60 @@
+use bincode::{Decode, Encode};
+use thiserror::Error;
+
+/// Version of the wire protocol spoken by this build.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest protocol version a server built from this crate still accepts.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Why a server refused a connection request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Error)]
+pub enum RejectReason {
+    #[error("protocol version {client} is not supported, server accepts {min} to {max}")]
+    VersionMismatch { client: u16, min: u16, max: u16 },
+    #[error("protocol id does not match")]
+    ProtocolMismatch,
+    #[error("server is full")]
+    ServerFull,
+    #[error("connect token is missing, invalid or expired")]
+    InvalidConnectToken,
+}
+
+/// Picks the protocol version used for a connection, given the newest version
+/// the client speaks.
+pub fn negotiate_version(client: u16) -> Result<u16, RejectReason> {
+    if client < MIN_PROTOCOL_VERSION {
+        return Err(RejectReason::VersionMismatch {
+            client,
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        });
+    }
+
+    Ok(client.min(PROTOCOL_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION),
+            Ok(PROTOCOL_VERSION),
+            "should accept the same version"
+        );
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 1),
+            Ok(PROTOCOL_VERSION),
+            "should downgrade newer clients"
+        );
+        assert!(
+            matches!(
+                negotiate_version(MIN_PROTOCOL_VERSION - 1),
+                Err(RejectReason::VersionMismatch { .. })
+            ),
+            "should reject versions older than the minimum"
+        );
+    }
+}
@@ -1,7 +1,14 @@
 use thiserror::Error;
 
+pub mod body;
+pub mod buffer;
+pub mod channel;
 pub mod encoding;
+pub mod fragment;
+pub mod handshake;
 pub mod packet;
+pub mod reliability;
+pub mod security;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -9,4 +16,8 @@ pub enum Error {
     PacketError(#[from] packet::Error),
     #[error("encoding error: {0}")]
     EncodingError(#[from] encoding::Error),
+    #[error("channel error: {0}")]
+    ChannelError(#[from] channel::Error),
+    #[error("security error: {0}")]
+    SecurityError(#[from] security::Error),
 }
@@ -2,9 +2,36 @@ use bincode::{Decode, Encode};
 use crc32fast::Hasher;
 use thiserror::Error;
 
+use crate::protocol::reliability::AckHeader;
+
 pub const PACKET_MAX_SIZE: usize = 1200;
 pub const PROTOCOL_ID: &[u8; 8] = b"UEUDP001";
 pub const END_CHECK: u32 = 0xFFFFFFFF;
+/// Version of the packet layout, bumped whenever the header changes.
+pub const HEADER_VERSION: u8 = 1;
+/// Bytes taken by the header: version, kind, flags, sequence, ack and ack
+/// bits.
+pub const HEADER_SIZE: usize = 1 + 1 + 1 + 2 + 2 + 4;
+/// Set in the packet flags when the payload is compressed with LZ4.
+pub const FLAG_COMPRESSED: u8 = 1 << 0;
+
+/// Most memory decoding a packet payload may claim. Limits count the memory
+/// of decoded values, larger than their encoding: a packet full of empty
+/// messages takes about ten times its size.
+pub const PACKET_DECODE_LIMIT: usize = PACKET_MAX_SIZE * 16;
+
+/// Bytes of a packet taken by the CRC, the header and the end check.
+const FRAMING_SIZE: usize = 4 + HEADER_SIZE + 4;
+
+/// Compression applied to packet payloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Fast compression, worth it for large payloads with repeated data like
+    /// game state snapshots.
+    Lz4,
+}
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -14,101 +41,400 @@ pub enum Error {
     CrcMismatch,
     #[error("invalid packet end")]
     InvalidEnd,
+    #[error("unsupported packet header version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown packet kind {0}")]
+    UnknownKind(u8),
+    #[error("unknown packet flags {0:#04x}")]
+    UnknownFlags(u8),
+    #[error("failed to decompress payload")]
+    DecompressionFailed,
     #[error("encode error: {0}")]
     EncodeError(#[from] bincode::error::EncodeError),
     #[error("decode error: {0}")]
     DecodeError(#[from] bincode::error::DecodeError),
 }
 
+/// What a packet carries, telling receivers how to decode its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PacketKind {
+    /// Handshake packet.
+    Connect = 0,
+    /// Application messages.
+    Data = 1,
+    /// Acknowledgements only, keeping the connection alive.
+    Heartbeat = 2,
+    Disconnect = 3,
+}
+
+impl TryFrom<u8> for PacketKind {
+    type Error = Error;
+
+    fn try_from(kind: u8) -> Result<Self, Error> {
+        match kind {
+            0 => Ok(PacketKind::Connect),
+            1 => Ok(PacketKind::Data),
+            2 => Ok(PacketKind::Heartbeat),
+            3 => Ok(PacketKind::Disconnect),
+            _ => Err(Error::UnknownKind(kind)),
+        }
+    }
+}
+
+/// Header encoded before the payload of every packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub kind: PacketKind,
+    pub flags: u8,
+    pub sequence: u16,
+    /// Most recent packet sequence received from the peer.
+    pub ack: u16,
+    /// Bit `n` is set when packet `ack - 1 - n` was received.
+    pub ack_bits: u32,
+}
+
+impl PacketHeader {
+    pub fn new(kind: PacketKind) -> Self {
+        Self {
+            kind,
+            flags: 0,
+            sequence: 0,
+            ack: 0,
+            ack_bits: 0,
+        }
+    }
+
+    /// Sets the sequence and acknowledgements of the packet.
+    pub fn with_ack(mut self, ack: AckHeader) -> Self {
+        self.sequence = ack.sequence;
+        self.ack = ack.ack;
+        self.ack_bits = ack.ack_bits;
+        self
+    }
+
+    pub fn ack_header(&self) -> AckHeader {
+        AckHeader {
+            sequence: self.sequence,
+            ack: self.ack,
+            ack_bits: self.ack_bits,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[0] = HEADER_VERSION;
+        bytes[1] = self.kind as u8;
+        bytes[2] = self.flags;
+        bytes[3..5].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[5..7].copy_from_slice(&self.ack.to_le_bytes());
+        bytes[7..11].copy_from_slice(&self.ack_bits.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Result<Self, Error> {
+        if bytes[0] != HEADER_VERSION {
+            return Err(Error::UnsupportedVersion(bytes[0]));
+        }
+        let flags = bytes[2];
+        if flags & !FLAG_COMPRESSED != 0 {
+            return Err(Error::UnknownFlags(flags));
+        }
+
+        Ok(Self {
+            kind: PacketKind::try_from(bytes[1])?,
+            flags,
+            sequence: u16::from_le_bytes([bytes[3], bytes[4]]),
+            ack: u16::from_le_bytes([bytes[5], bytes[6]]),
+            ack_bits: u32::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Packet {
     data: Vec<u8>,
 }
 
 impl Packet {
-    pub fn from_data<T: Encode>(value: T) -> Result<Packet, Error> {
-        let mut payload = Vec::new();
-        bincode::encode_into_std_write(value, &mut payload, bincode::config::standard())?;
+    /// Wraps a received datagram, validated when decoded with
+    /// [`Packet::to_data`].
+    pub fn from_bytes(data: Vec<u8>) -> Packet {
+        Packet { data }
+    }
 
-        let overhead = 4 + 4; // CRC + end_check
-        if payload.len() + overhead > PACKET_MAX_SIZE {
-            return Err(Error::PayloadTooLarge);
-        }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
 
-        let mut crc_input = Vec::new();
-        crc_input.extend_from_slice(PROTOCOL_ID);
-        crc_input.extend(&payload);
-        crc_input.extend_from_slice(&END_CHECK.to_le_bytes());
+    pub fn from_data<T: Encode>(header: PacketHeader, value: T) -> Result<Packet, Error> {
+        Packet::from_data_with(header, value, Compression::None)
+    }
 
-        let mut hasher = Hasher::new();
-        hasher.update(&crc_input);
-        let crc = hasher.finalize();
+    /// Encodes a value, compressing the payload when it makes it smaller.
+    ///
+    /// Allocates the packet, see [`encode_into`] to reuse a buffer instead.
+    pub fn from_data_with<T: Encode>(
+        header: PacketHeader,
+        value: T,
+        compression: Compression,
+    ) -> Result<Packet, Error> {
+        let mut data = Vec::new();
+        encode_into(header, value, compression, &mut data)?;
+        Ok(Packet { data })
+    }
 
-        let mut buf = Vec::new();
-        buf.extend_from_slice(&crc.to_le_bytes());
-        buf.extend(&payload);
-        buf.extend_from_slice(&END_CHECK.to_le_bytes());
+    /// Parses and validates the header, without checking the rest of the
+    /// packet.
+    pub fn header(&self) -> Result<PacketHeader, Error> {
+        decode_header(&self.data)
+    }
 
-        Ok(Packet { data: buf })
+    /// Whether the payload of the packet is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.header()
+            .is_ok_and(|header| header.flags & FLAG_COMPRESSED != 0)
     }
 
     pub fn to_data<T: Decode<()>>(&self) -> Result<T, Error> {
-        if self.data.len() < 8 {
-            return Err(Error::InvalidEnd);
-        }
+        decode_from(&self.data).map(|(_, value)| value)
+    }
+}
 
-        let crc_bytes = &self.data[..4];
-        let payload = &self.data[4..self.data.len() - 4];
-        let end_bytes = &self.data[self.data.len() - 4..];
+/// Encodes a packet into `buf`, replacing its content.
+///
+/// Nothing is allocated once `buf` has the capacity of a packet. The size
+/// limit applies to the uncompressed payload, so that receivers never have to
+/// inflate more than a packet.
+pub fn encode_into<T: Encode>(
+    mut header: PacketHeader,
+    value: T,
+    compression: Compression,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error> {
+    const PAYLOAD_START: usize = 4 + HEADER_SIZE;
 
-        if u32::from_le_bytes(end_bytes.try_into().unwrap()) != END_CHECK {
-            return Err(Error::InvalidEnd);
+    buf.clear();
+    buf.resize(PAYLOAD_START, 0);
+    bincode::encode_into_std_write(value, buf, bincode::config::standard())?;
+
+    let payload_len = buf.len() - PAYLOAD_START;
+    if payload_len + FRAMING_SIZE > PACKET_MAX_SIZE {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    header.flags &= !FLAG_COMPRESSED;
+    if compression == Compression::Lz4 {
+        let mut compressed = [0u8; 4 + lz4_flex::block::get_maximum_output_size(PACKET_MAX_SIZE)];
+        compressed[..4].copy_from_slice(&(payload_len as u32).to_le_bytes());
+        if let Ok(len) = lz4_flex::block::compress_into(&buf[PAYLOAD_START..], &mut compressed[4..])
+        {
+            if 4 + len < payload_len {
+                buf.truncate(PAYLOAD_START);
+                buf.extend_from_slice(&compressed[..4 + len]);
+                header.flags |= FLAG_COMPRESSED;
+            }
         }
+    }
+
+    buf[4..PAYLOAD_START].copy_from_slice(&header.to_bytes());
+    buf.extend_from_slice(&END_CHECK.to_le_bytes());
+    let crc = checksum(&buf[4..]);
+    buf[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(())
+}
+
+/// Parses and validates the header of a packet, without checking the rest
+/// of it.
+pub fn decode_header(data: &[u8]) -> Result<PacketHeader, Error> {
+    let bytes = data
+        .get(4..4 + HEADER_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::InvalidEnd)?;
+    PacketHeader::from_bytes(bytes)
+}
 
-        let mut crc_input = Vec::new();
-        crc_input.extend_from_slice(PROTOCOL_ID);
-        crc_input.extend(payload);
-        crc_input.extend_from_slice(&END_CHECK.to_le_bytes());
+/// Validates a packet and decodes its header and payload, borrowing the
+/// received datagram.
+pub fn decode_from<T: Decode<()>>(data: &[u8]) -> Result<(PacketHeader, T), Error> {
+    if data.len() < FRAMING_SIZE {
+        return Err(Error::InvalidEnd);
+    }
 
-        let mut hasher = Hasher::new();
-        hasher.update(&crc_input);
-        let crc_calc = hasher.finalize();
-        let crc_orig = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+    let crc_bytes = &data[..4];
+    let end_bytes = &data[data.len() - 4..];
+    if u32::from_le_bytes(end_bytes.try_into().unwrap()) != END_CHECK {
+        return Err(Error::InvalidEnd);
+    }
 
-        if crc_calc != crc_orig {
-            return Err(Error::CrcMismatch);
-        }
+    let crc_orig = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+    if checksum(&data[4..]) != crc_orig {
+        return Err(Error::CrcMismatch);
+    }
+
+    let header = decode_header(data)?;
+    let payload = &data[4 + HEADER_SIZE..data.len() - 4];
+    // Only zeroed when needed, uncompressed packets are decoded in place
+    let mut decompressed;
+    let payload = if header.flags & FLAG_COMPRESSED != 0 {
+        decompressed = [0u8; PACKET_MAX_SIZE];
+        let len = decompress(payload, &mut decompressed)?;
+        &decompressed[..len]
+    } else {
+        payload
+    };
+
+    let value = bincode::decode_from_slice(payload, decode_config()).map(|(v, _)| v)?;
+    Ok((header, value))
+}
+
+/// Configuration decoding untrusted payloads, so that a forged length prefix
+/// can not make the decoder allocate more than a packet can carry.
+fn decode_config() -> impl bincode::config::Config {
+    bincode::config::standard().with_limit::<{ PACKET_DECODE_LIMIT }>()
+}
+
+/// CRC of everything after the CRC itself, salted with the protocol id so
+/// that packets of other protocols are rejected.
+fn checksum(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(PROTOCOL_ID);
+    hasher.update(data);
+    hasher.finalize()
+}
 
-        let value =
-            bincode::decode_from_slice(payload, bincode::config::standard()).map(|(v, _)| v)?;
-        Ok(value)
+/// Inflates an LZ4 payload into `out`, refusing to produce more than a
+/// packet.
+fn decompress(payload: &[u8], out: &mut [u8; PACKET_MAX_SIZE]) -> Result<usize, Error> {
+    let (size, compressed) = payload
+        .split_first_chunk::<4>()
+        .ok_or(Error::DecompressionFailed)?;
+    let size = u32::from_le_bytes(*size) as usize;
+    if size > PACKET_MAX_SIZE {
+        return Err(Error::DecompressionFailed);
+    }
+
+    match lz4_flex::block::decompress_into(compressed, &mut out[..size]) {
+        Ok(len) if len == size => Ok(len),
+        _ => Err(Error::DecompressionFailed),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::reliability::Message;
 
-    const LARGE_PACKET_SIZE: usize = PACKET_MAX_SIZE * 2;
+    const LARGE_PACKET_SIZE: usize = PACKET_MAX_SIZE * 16;
+    const HEADER: PacketHeader = PacketHeader {
+        kind: PacketKind::Data,
+        flags: 0,
+        sequence: 7,
+        ack: 3,
+        ack_bits: 0b101,
+    };
 
     #[test]
     fn test_packet_roundtrip() {
         let original = "hello world".to_string();
-        let packet =
-            Packet::from_data(original.clone()).expect("should be able to create packet from data");
+        let packet = Packet::from_data(HEADER, original.clone())
+            .expect("should be able to create packet from data");
+        assert_eq!(
+            packet.header().ok(),
+            Some(HEADER),
+            "header should match original"
+        );
         let decoded: String = packet
             .to_data()
             .expect("should be able to decode packet back to original data");
         assert_eq!(original, decoded, "decoded value should match original");
     }
 
+    #[test]
+    fn test_invalid_header() {
+        let packet = Packet::from_data(HEADER, 1u8).expect("should be able to create packet");
+        let with_header_byte = |index: usize, value: u8| {
+            let mut data = packet.as_bytes().to_vec();
+            data[4 + index] = value;
+            let crc = checksum(&data[4..]);
+            data[..4].copy_from_slice(&crc.to_le_bytes());
+            Packet::from_bytes(data).to_data::<u8>()
+        };
+
+        assert!(
+            matches!(with_header_byte(0, 9), Err(Error::UnsupportedVersion(9))),
+            "should reject other header versions"
+        );
+        assert!(
+            matches!(with_header_byte(1, 9), Err(Error::UnknownKind(9))),
+            "should reject unknown kinds"
+        );
+        assert!(
+            matches!(with_header_byte(2, 0x80), Err(Error::UnknownFlags(0x80))),
+            "should reject unknown flags"
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let snapshot = vec![7u8; 800];
+        let packet = Packet::from_data_with(HEADER, snapshot.clone(), Compression::Lz4)
+            .expect("should be able to create packet from data");
+        assert!(packet.is_compressed(), "should flag the compressed payload");
+        assert!(
+            packet.as_bytes().len() < snapshot.len() / 2,
+            "should shrink repetitive payloads"
+        );
+        let decoded: Vec<u8> = packet
+            .to_data()
+            .expect("should be able to decompress the payload");
+        assert_eq!(decoded, snapshot, "decoded value should match original");
+
+        let packet = Packet::from_data_with(HEADER, 1u8, Compression::Lz4)
+            .expect("should be able to create packet from data");
+        assert!(
+            !packet.is_compressed(),
+            "should not compress when it does not help"
+        );
+    }
+
     #[test]
     fn test_payload_too_large() {
         let large = vec![0u8; LARGE_PACKET_SIZE]; // exceeds allowed
-        let result = Packet::from_data(large);
+        let result = Packet::from_data(HEADER, large);
         assert!(
             matches!(result, Err(Error::PayloadTooLarge)),
             "should return PayloadTooLarge error when data exceeds maximum size"
         );
     }
+
+    #[test]
+    fn test_decode_limit() {
+        // A forged length prefix, claiming a huge vector follows
+        let packet = Packet::from_data(HEADER, 1u64 << 36)
+            .expect("should be able to create packet from data");
+        assert!(
+            matches!(packet.to_data::<Vec<u64>>(), Err(Error::DecodeError(_))),
+            "should refuse to allocate more than a packet can carry"
+        );
+
+        let mut messages = Vec::new();
+        let empty = Message {
+            channel: 0,
+            id: 0,
+            fragment: None,
+            data: Vec::new(),
+        };
+        while Packet::from_data(HEADER, &messages).is_ok() {
+            messages.push(empty.clone());
+        }
+        messages.pop();
+        let packet = Packet::from_data(HEADER, &messages)
+            .expect("should be able to create packet from data");
+        assert_eq!(
+            packet.to_data::<Vec<Message>>().ok(),
+            Some(messages),
+            "should decode packets full of small messages"
+        );
+    }
 }
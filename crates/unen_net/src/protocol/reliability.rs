@@ -0,0 +1,799 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use bincode::{Decode, Encode};
+
+use crate::protocol::{
+    body::PacketBody,
+    channel::{self, ChannelConfig, ChannelId, ReceiveChannel, SendChannel},
+    fragment::{
+        FragmentHeader, Reassembler, DEFAULT_MAX_REASSEMBLY_BUFFERS, DEFAULT_REASSEMBLY_TIMEOUT,
+    },
+    packet::PACKET_MAX_SIZE,
+};
+
+/// Bytes of a packet taken by the framing, the ack header and the message
+/// count.
+const PACKET_OVERHEAD: usize = 32;
+/// Bytes taken by the channel, id, fragment header and length of a message.
+const MESSAGE_HEADER_SIZE: usize = 16;
+/// Largest message fitting a packet alone, larger messages are fragmented.
+pub const FRAGMENT_SIZE: usize = PACKET_MAX_SIZE - PACKET_OVERHEAD - MESSAGE_HEADER_SIZE;
+/// How many packets are written at most at once, messages that do not fit
+/// wait for the next write.
+pub const MAX_PACKETS_PER_WRITE: usize = 32;
+/// How many sent and received packet sequences are remembered.
+const SEQUENCE_BUFFER_SIZE: usize = 1024;
+/// After how long an unacknowledged packet counts as lost.
+pub const PACKET_LOSS_TIMEOUT: Duration = Duration::from_secs(1);
+/// Weight of a new sample in the smoothed round-trip time and jitter.
+const RTT_SMOOTHING: f32 = 0.1;
+/// Weight of a new sample in the smoothed packet loss.
+const PACKET_LOSS_SMOOTHING: f32 = 0.05;
+
+/// Returns whether sequence `a` is more recent than `b`, handling wrap
+/// around.
+pub fn sequence_greater_than(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < u16::MAX / 2 + 1
+}
+
+/// Sequence of a packet along with the acknowledgement of the packets
+/// received from the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckHeader {
+    pub sequence: u16,
+    /// Most recent packet sequence received from the peer.
+    pub ack: u16,
+    /// Bit `n` is set when packet `ack - 1 - n` was received.
+    pub ack_bits: u32,
+}
+
+/// Message carried by a data packet.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Message {
+    pub channel: u8,
+    /// Per-channel message id, used for ordering and deduplication.
+    pub id: u16,
+    /// Set when the message is a fragment of a larger one.
+    pub fragment: Option<FragmentHeader>,
+    pub data: Vec<u8>,
+}
+
+/// Fixed size buffer indexed by wrapping sequence numbers.
+#[derive(Debug, Clone)]
+struct SequenceBuffer<T> {
+    entries: Vec<Option<(u16, T)>>,
+}
+
+impl<T> SequenceBuffer<T> {
+    fn new(size: usize) -> Self {
+        Self {
+            entries: (0..size).map(|_| None).collect(),
+        }
+    }
+
+    fn index(&self, sequence: u16) -> usize {
+        sequence as usize % self.entries.len()
+    }
+
+    fn insert(&mut self, sequence: u16, value: T) {
+        let index = self.index(sequence);
+        self.entries[index] = Some((sequence, value));
+    }
+
+    fn contains(&self, sequence: u16) -> bool {
+        matches!(self.entries[self.index(sequence)], Some((stored, _)) if stored == sequence)
+    }
+
+    fn get_mut(&mut self, sequence: u16) -> Option<&mut T> {
+        let index = self.index(sequence);
+        match &mut self.entries[index] {
+            Some((stored, value)) if *stored == sequence => Some(value),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, sequence: u16) -> Option<T> {
+        if !self.contains(sequence) {
+            return None;
+        }
+        let index = self.index(sequence);
+        self.entries[index].take().map(|(_, value)| value)
+    }
+}
+
+/// Packet written by an endpoint, remembered until acknowledged or lost.
+#[derive(Debug, Clone)]
+struct SentPacket {
+    sent_at: Instant,
+    acked: bool,
+    /// Channel and id of the messages carried.
+    messages: Vec<(u8, u16)>,
+}
+
+/// Groups messages into as few packets as possible.
+#[derive(Debug, Default)]
+struct Packer {
+    packets: Vec<Vec<Message>>,
+    /// Bytes used in the last packet.
+    size: usize,
+}
+
+impl Packer {
+    /// Adds a message to the last packet or a new one, giving it back when
+    /// the packet limit is reached.
+    fn push(&mut self, message: Message) -> Result<(), Message> {
+        let len = message.data.len() + MESSAGE_HEADER_SIZE;
+        if self.packets.is_empty() || self.size + len > PACKET_MAX_SIZE - PACKET_OVERHEAD {
+            if self.packets.len() >= MAX_PACKETS_PER_WRITE {
+                return Err(message);
+            }
+            self.packets.push(Vec::new());
+            self.size = 0;
+        }
+
+        self.size += len;
+        self.packets.last_mut().unwrap().push(message);
+        Ok(())
+    }
+}
+
+/// Sequencing, acknowledgement, retransmission and channel multiplexing
+/// state of one side of a connection.
+///
+/// The endpoint does no IO: messages are queued with [`send`](Self::send),
+/// packets to transmit are produced by [`write_packets`](Self::write_packets)
+/// and received packets are fed to [`process`](Self::process).
+#[derive(Debug, Clone)]
+pub struct ReliableEndpoint {
+    resend_interval: Duration,
+    /// Sequence of the next packet written.
+    sequence: u16,
+    sent: SequenceBuffer<SentPacket>,
+    /// Sent packets not counted as delivered or lost yet, oldest first.
+    in_flight: VecDeque<u16>,
+    /// Smoothed round-trip time in seconds, unknown until the first ack.
+    rtt: Option<f32>,
+    /// Smoothed round-trip time variation in seconds.
+    jitter: f32,
+    /// Smoothed fraction of sent packets lost.
+    packet_loss: f32,
+    /// Most recent packet sequence received.
+    remote_sequence: u16,
+    received: SequenceBuffer<()>,
+    /// Whether received messages were not acknowledged yet.
+    ack_pending: bool,
+    /// Ordered by descending priority.
+    send_channels: Vec<SendChannel>,
+    receive_channels: HashMap<u8, ReceiveChannel>,
+    reassembler: Reassembler,
+    delivered: VecDeque<(ChannelId, Vec<u8>)>,
+}
+
+impl ReliableEndpoint {
+    /// Creates an endpoint with the given channels, resending unacknowledged
+    /// reliable messages every `resend_interval`.
+    pub fn new(
+        resend_interval: Duration,
+        channels: impl IntoIterator<Item = (ChannelId, ChannelConfig)>,
+    ) -> Self {
+        let mut send_channels: Vec<SendChannel> = channels
+            .into_iter()
+            .map(|(id, config)| SendChannel::new(id, config))
+            .collect();
+        send_channels.sort_by_key(|channel| (u8::MAX - channel.config().priority(), channel.id()));
+        let receive_channels = send_channels
+            .iter()
+            .map(|channel| {
+                (
+                    channel.id().raw(),
+                    ReceiveChannel::new(channel.config().kind()),
+                )
+            })
+            .collect();
+
+        Self {
+            resend_interval,
+            sequence: 0,
+            sent: SequenceBuffer::new(SEQUENCE_BUFFER_SIZE),
+            in_flight: VecDeque::new(),
+            rtt: None,
+            jitter: 0.0,
+            packet_loss: 0.0,
+            remote_sequence: u16::MAX,
+            received: SequenceBuffer::new(SEQUENCE_BUFFER_SIZE),
+            ack_pending: false,
+            send_channels,
+            receive_channels,
+            reassembler: Reassembler::new(
+                DEFAULT_MAX_REASSEMBLY_BUFFERS,
+                DEFAULT_REASSEMBLY_TIMEOUT,
+            ),
+            delivered: VecDeque::new(),
+        }
+    }
+
+    /// Sets how many incomplete messages are kept at once, and after how long
+    /// they are discarded.
+    pub fn with_reassembly(mut self, max_buffers: usize, timeout: Duration) -> Self {
+        self.reassembler = Reassembler::new(max_buffers, timeout);
+        self
+    }
+
+    /// Queues a message on a channel, sent by the next
+    /// [`write_packets`](Self::write_packets).
+    pub fn send(&mut self, channel: ChannelId, data: &[u8]) -> Result<(), channel::Error> {
+        self.send_channels
+            .iter_mut()
+            .find(|send| send.id() == channel)
+            .ok_or(channel::Error::UnknownChannel(channel))?
+            .push(data)
+    }
+
+    /// Produces the packets to transmit, packing the messages due on every
+    /// channel by priority, or a bare acknowledgement when messages were
+    /// received but nothing else is sent.
+    pub fn write_packets(&mut self, now: Instant) -> Vec<PacketBody> {
+        let mut packer = Packer::default();
+        for channel in &mut self.send_channels {
+            channel.write(now, self.resend_interval, |message| packer.push(message));
+        }
+
+        if packer.packets.is_empty() && self.ack_pending {
+            return vec![self.write_packet(Vec::new(), now)];
+        }
+        packer
+            .packets
+            .into_iter()
+            .map(|messages| self.write_packet(messages, now))
+            .collect()
+    }
+
+    /// Produces a packet carrying no message, keeping the connection alive
+    /// and acknowledging received packets.
+    pub fn write_heartbeat(&mut self, now: Instant) -> PacketBody {
+        self.write_packet(Vec::new(), now)
+    }
+
+    /// Handles a received data packet, returns `false` when it was a
+    /// duplicate or too old and got ignored.
+    pub fn process(&mut self, header: AckHeader, messages: Vec<Message>, now: Instant) -> bool {
+        if !self.record_received(header.sequence) {
+            return false;
+        }
+
+        self.process_ack(header.ack, now);
+        for bit in 0..32 {
+            if header.ack_bits & (1 << bit) != 0 {
+                self.process_ack(header.ack.wrapping_sub(bit + 1), now);
+            }
+        }
+
+        if !messages.is_empty() {
+            self.ack_pending = true;
+        }
+        self.reassembler.expire(now);
+        for message in messages {
+            let Some(channel) = self.receive_channels.get_mut(&message.channel) else {
+                continue;
+            };
+            channel.receive(message, |message| {
+                let id = ChannelId::new(message.channel);
+                let data = match message.fragment {
+                    None => Some(message.data),
+                    Some(fragment) => self.reassembler.receive(
+                        message.channel,
+                        message.id,
+                        fragment,
+                        message.data,
+                        now,
+                    ),
+                };
+                if let Some(data) = data {
+                    self.delivered.push_back((id, data));
+                }
+            });
+        }
+        for (&id, channel) in &self.receive_channels {
+            if let Some(receive_id) = channel.receive_id() {
+                self.reassembler.discard_delivered(id, receive_id);
+            }
+        }
+        true
+    }
+
+    /// Takes the messages received since the last call, in the order their
+    /// channel guarantees.
+    pub fn drain_received(&mut self) -> impl Iterator<Item = (ChannelId, Vec<u8>)> + '_ {
+        self.delivered.drain(..)
+    }
+
+    /// Amount of reliable messages not acknowledged yet, on every channel.
+    pub fn unacked_count(&self) -> usize {
+        self.send_channels
+            .iter()
+            .map(SendChannel::unacked_count)
+            .sum()
+    }
+
+    /// Smoothed round-trip time, zero until the first acknowledgement.
+    pub fn rtt(&self) -> Duration {
+        Duration::from_secs_f32(self.rtt.unwrap_or_default())
+    }
+
+    /// Smoothed variation of the round-trip time.
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f32(self.jitter)
+    }
+
+    /// Smoothed percentage of sent packets that were never acknowledged.
+    pub fn packet_loss(&self) -> f32 {
+        self.packet_loss * 100.0
+    }
+
+    /// Amount of reliable messages sent again because their acknowledgement
+    /// did not arrive in time.
+    pub fn resent_count(&self) -> u64 {
+        self.send_channels
+            .iter()
+            .map(SendChannel::resent_count)
+            .sum()
+    }
+
+    fn write_packet(&mut self, messages: Vec<Message>, now: Instant) -> PacketBody {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.ack_pending = false;
+
+        let messages_ids = messages
+            .iter()
+            .map(|message| (message.channel, message.id))
+            .collect();
+        self.sent.insert(
+            sequence,
+            SentPacket {
+                sent_at: now,
+                acked: false,
+                messages: messages_ids,
+            },
+        );
+        self.in_flight.push_back(sequence);
+        self.update_packet_loss(now);
+
+        PacketBody::Data {
+            header: AckHeader {
+                sequence,
+                ack: self.remote_sequence,
+                ack_bits: self.ack_bits(),
+            },
+            messages,
+        }
+    }
+
+    fn ack_bits(&self) -> u32 {
+        (0..32)
+            .filter(|&bit| {
+                self.received
+                    .contains(self.remote_sequence.wrapping_sub(bit + 1))
+            })
+            .fold(0, |bits, bit| bits | (1 << bit))
+    }
+
+    fn record_received(&mut self, sequence: u16) -> bool {
+        if self.received.contains(sequence) {
+            return false;
+        }
+
+        if sequence_greater_than(sequence, self.remote_sequence) {
+            // Forget whatever was stored for the sequences skipped over
+            let skipped = sequence.wrapping_sub(self.remote_sequence) as usize;
+            for offset in 1..skipped.min(SEQUENCE_BUFFER_SIZE) {
+                self.received
+                    .remove(self.remote_sequence.wrapping_add(offset as u16));
+            }
+            self.remote_sequence = sequence;
+        } else if self.remote_sequence.wrapping_sub(sequence) as usize >= SEQUENCE_BUFFER_SIZE {
+            return false;
+        }
+
+        self.received.insert(sequence, ());
+        true
+    }
+
+    fn process_ack(&mut self, sequence: u16, now: Instant) {
+        let Some(packet) = self.sent.get_mut(sequence) else {
+            return;
+        };
+        if packet.acked {
+            return;
+        }
+        packet.acked = true;
+        let sample = now.duration_since(packet.sent_at).as_secs_f32();
+        let messages = std::mem::take(&mut packet.messages);
+
+        match self.rtt {
+            None => self.rtt = Some(sample),
+            Some(rtt) => {
+                self.jitter += ((sample - rtt).abs() - self.jitter) * RTT_SMOOTHING;
+                self.rtt = Some(rtt + (sample - rtt) * RTT_SMOOTHING);
+            }
+        }
+
+        for (channel, id) in messages {
+            if let Some(send) = self
+                .send_channels
+                .iter_mut()
+                .find(|send| send.id().raw() == channel)
+            {
+                send.ack(id);
+            }
+        }
+    }
+
+    /// Counts the packets acknowledged or lost since the last update in the
+    /// packet loss.
+    fn update_packet_loss(&mut self, now: Instant) {
+        while let Some(&sequence) = self.in_flight.front() {
+            let lost = match self.sent.get_mut(sequence) {
+                Some(packet) if packet.acked => 0.0,
+                Some(packet) if now.duration_since(packet.sent_at) >= PACKET_LOSS_TIMEOUT => 1.0,
+                Some(_) => return,
+                // Overwritten by a newer packet without being acknowledged
+                None => 1.0,
+            };
+            self.in_flight.pop_front();
+            self.packet_loss += (lost - self.packet_loss) * PACKET_LOSS_SMOOTHING;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::protocol::{
+        channel::ChannelKind,
+        fragment::{MAX_FRAGMENT_COUNT, MESSAGE_MAX_SIZE},
+    };
+
+    /// Hands each message to `receiver` in its own packet.
+    fn process_each(
+        receiver: &mut ReliableEndpoint,
+        messages: impl IntoIterator<Item = Message>,
+        now: Instant,
+    ) {
+        let mut sender = endpoint();
+        for message in messages {
+            let PacketBody::Data { header, .. } = sender.write_heartbeat(now) else {
+                panic!("should write data packets");
+            };
+            receiver.process(header, vec![message], now);
+        }
+    }
+
+    const RELIABLE: ChannelId = ChannelId::new(0);
+    const UNRELIABLE: ChannelId = ChannelId::new(1);
+    const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+    const TICK: Duration = Duration::from_millis(10);
+
+    /// In-process link dropping, duplicating and reordering packets.
+    struct LossyLink {
+        rng: StdRng,
+        loss: f64,
+        in_flight: Vec<PacketBody>,
+    }
+
+    impl LossyLink {
+        fn new(seed: u64, loss: f64) -> Self {
+            Self {
+                rng: StdRng::seed_from_u64(seed),
+                loss,
+                in_flight: Vec::new(),
+            }
+        }
+
+        fn transmit(&mut self, packets: Vec<PacketBody>) {
+            for packet in packets {
+                if self.rng.random_bool(self.loss) {
+                    continue;
+                }
+                if self.rng.random_bool(0.1) {
+                    self.in_flight.push(packet.clone());
+                }
+                self.in_flight.push(packet);
+            }
+        }
+
+        fn deliver(&mut self, endpoint: &mut ReliableEndpoint, now: Instant) {
+            let mut packets = std::mem::take(&mut self.in_flight);
+            if packets.len() > 1 {
+                let index = self.rng.random_range(0..packets.len());
+                packets.swap(0, index);
+            }
+            for packet in packets {
+                let PacketBody::Data { header, messages } = packet else {
+                    panic!("should only carry data packets");
+                };
+                endpoint.process(header, messages, now);
+            }
+        }
+    }
+
+    fn endpoint() -> ReliableEndpoint {
+        ReliableEndpoint::new(
+            RESEND_INTERVAL,
+            [
+                (RELIABLE, ChannelConfig::new(ChannelKind::ReliableOrdered)),
+                (
+                    UNRELIABLE,
+                    ChannelConfig::new(ChannelKind::Unreliable).with_priority(1),
+                ),
+            ],
+        )
+    }
+
+    /// Runs both endpoints over lossy links, calling `tick` before each step,
+    /// until `sender` has nothing left to resend. Returns what `receiver` got.
+    fn exchange(
+        sender: &mut ReliableEndpoint,
+        receiver: &mut ReliableEndpoint,
+        loss: f64,
+        mut tick: impl FnMut(&mut ReliableEndpoint, usize),
+    ) -> Vec<(ChannelId, Vec<u8>)> {
+        let mut forward = LossyLink::new(1, loss);
+        let mut backward = LossyLink::new(2, loss);
+        let mut now = Instant::now();
+        let mut received = Vec::new();
+        for i in 0..1000 {
+            tick(sender, i);
+            forward.transmit(sender.write_packets(now));
+            forward.deliver(receiver, now);
+            received.extend(receiver.drain_received());
+
+            backward.transmit(receiver.write_packets(now));
+            backward.deliver(sender, now);
+
+            if i >= 100 && sender.unacked_count() == 0 {
+                break;
+            }
+            now += TICK;
+        }
+        received
+    }
+
+    #[test]
+    fn test_sequence_greater_than() {
+        assert!(sequence_greater_than(1, 0), "1 should be after 0");
+        assert!(!sequence_greater_than(0, 1), "0 should be before 1");
+        assert!(
+            sequence_greater_than(0, u16::MAX),
+            "0 should be after the wrap"
+        );
+        assert!(!sequence_greater_than(5, 5), "should not be after itself");
+    }
+
+    #[test]
+    fn test_reliable_over_lossy_link() {
+        let mut sender = endpoint();
+        let mut receiver = endpoint();
+        let sent: Vec<Vec<u8>> = (0..2000u16).map(|i| i.to_le_bytes().to_vec()).collect();
+
+        let received = exchange(&mut sender, &mut receiver, 0.3, |sender, i| {
+            for data in sent.iter().skip(i * 20).take(20) {
+                sender
+                    .send(RELIABLE, data)
+                    .expect("should be able to queue");
+            }
+        });
+
+        assert_eq!(sender.unacked_count(), 0, "should have everything acked");
+        let received: Vec<Vec<u8>> = received.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(received, sent, "should arrive exactly once and in order");
+    }
+
+    #[test]
+    fn test_fragments_over_lossy_link() {
+        let mut sender = endpoint();
+        let mut receiver = endpoint();
+        let sent: Vec<Vec<u8>> = [20_000, 100, FRAGMENT_SIZE + 1]
+            .into_iter()
+            .map(|len| (0..len).map(|i| (i % 251) as u8).collect())
+            .collect();
+
+        let received = exchange(&mut sender, &mut receiver, 0.3, |sender, i| {
+            if i == 0 {
+                for data in &sent {
+                    sender
+                        .send(RELIABLE, data)
+                        .expect("should be able to queue");
+                }
+            }
+        });
+
+        let received: Vec<Vec<u8>> = received.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(received, sent, "should reassemble large messages in order");
+    }
+
+    #[test]
+    fn test_unreliable_is_not_resent() {
+        let mut sender = endpoint();
+        let mut receiver = endpoint();
+
+        let received = exchange(&mut sender, &mut receiver, 0.3, |sender, i| {
+            if i < 100 {
+                sender
+                    .send(UNRELIABLE, &[i as u8])
+                    .expect("should be able to queue");
+            }
+        });
+
+        assert!(
+            received.len() < 100,
+            "lost unreliable messages should not be resent"
+        );
+        let mut ids: Vec<u8> = received.iter().map(|(_, data)| data[0]).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), received.len(), "should not deliver duplicates");
+    }
+
+    #[test]
+    fn test_rtt_and_packet_loss() {
+        let mut sender = endpoint();
+        let mut receiver = endpoint();
+        let start = Instant::now();
+
+        let PacketBody::Data { header, messages } = sender.write_heartbeat(start) else {
+            panic!("should write data packets");
+        };
+        receiver.process(header, messages, start);
+        let PacketBody::Data { header, messages } = receiver.write_heartbeat(start) else {
+            panic!("should write data packets");
+        };
+        sender.process(header, messages, start + Duration::from_millis(40));
+        assert!(
+            sender.rtt().abs_diff(Duration::from_millis(40)) < Duration::from_micros(10),
+            "should measure the round trip from the ack"
+        );
+
+        let mut forward = LossyLink::new(3, 0.25);
+        let mut backward = LossyLink::new(4, 0.0);
+        let mut now = start;
+        for _ in 0..1000 {
+            now += TICK;
+            forward.transmit(vec![sender.write_heartbeat(now)]);
+            forward.deliver(&mut receiver, now);
+            backward.transmit(vec![receiver.write_heartbeat(now)]);
+            backward.deliver(&mut sender, now);
+        }
+        assert!(
+            (15.0..35.0).contains(&sender.packet_loss()),
+            "should estimate the packet loss, got {}%",
+            sender.packet_loss()
+        );
+    }
+
+    #[test]
+    fn test_packing_and_priority() {
+        let mut endpoint = endpoint();
+        for _ in 0..MAX_PACKETS_PER_WRITE + 1 {
+            endpoint
+                .send(RELIABLE, &[0; FRAGMENT_SIZE])
+                .expect("should accept the largest message");
+        }
+        for i in 0..10 {
+            endpoint
+                .send(UNRELIABLE, &[i])
+                .expect("should be able to queue");
+        }
+
+        let now = Instant::now();
+        let packets = endpoint.write_packets(now);
+        assert_eq!(
+            packets.len(),
+            MAX_PACKETS_PER_WRITE,
+            "should stop at the packet limit"
+        );
+        let PacketBody::Data { messages, .. } = &packets[0] else {
+            panic!("should write data packets");
+        };
+        assert_eq!(messages.len(), 10, "should pack small messages together");
+        assert_eq!(
+            messages[0].channel,
+            UNRELIABLE.raw(),
+            "should write the higher priority channel first"
+        );
+        assert!(
+            packets.iter().all(|packet| packet.encode().is_ok()),
+            "packed messages should fit a packet"
+        );
+        // The small messages took one packet, leaving two large ones behind
+        assert_eq!(
+            endpoint.write_packets(now).len(),
+            2,
+            "should write the rest on the next write"
+        );
+    }
+
+    #[test]
+    fn test_unknown_channel() {
+        let mut endpoint = endpoint();
+        assert!(
+            matches!(
+                endpoint.send(ChannelId::new(7), b"hello"),
+                Err(channel::Error::UnknownChannel(_))
+            ),
+            "should refuse unconfigured channels"
+        );
+        assert!(
+            matches!(
+                endpoint.send(RELIABLE, &vec![0; MESSAGE_MAX_SIZE + 1]),
+                Err(channel::Error::MessageTooLarge(_))
+            ),
+            "should refuse messages with too many fragments"
+        );
+    }
+
+    #[test]
+    fn test_lone_reliable_fragments() {
+        let mut receiver = endpoint();
+        let now = Instant::now();
+        for id in 0..2000 {
+            let message = Message {
+                channel: RELIABLE.raw(),
+                id,
+                fragment: Some(FragmentHeader {
+                    index: 0,
+                    count: MAX_FRAGMENT_COUNT as u16,
+                }),
+                data: vec![0],
+            };
+            process_each(&mut receiver, [message], now);
+            assert!(
+                receiver.reassembler.len() <= DEFAULT_MAX_REASSEMBLY_BUFFERS,
+                "should bound the incomplete messages"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sequenced_fragments_out_of_order() {
+        let sequenced = ChannelId::new(2);
+        let channels = [(
+            sequenced,
+            ChannelConfig::new(ChannelKind::UnreliableSequenced),
+        )];
+        let mut sender = ReliableEndpoint::new(RESEND_INTERVAL, channels);
+        let mut receiver = ReliableEndpoint::new(RESEND_INTERVAL, channels);
+        let now = Instant::now();
+
+        let data: Vec<u8> = (0..FRAGMENT_SIZE * 3).map(|i| i as u8).collect();
+        sender
+            .send(sequenced, b"old")
+            .expect("should be able to queue");
+        sender
+            .send(sequenced, &data)
+            .expect("should be able to queue");
+        let mut messages: Vec<Message> = sender
+            .write_packets(now)
+            .into_iter()
+            .flat_map(|packet| match packet {
+                PacketBody::Data { messages, .. } => messages,
+                _ => panic!("should write data packets"),
+            })
+            .collect();
+        assert_eq!(messages.len(), 4, "should fragment the large message");
+        messages.reverse();
+        process_each(&mut receiver, messages, now);
+
+        assert_eq!(
+            receiver.drain_received().collect::<Vec<_>>(),
+            vec![(sequenced, data)],
+            "should reassemble the newest message and drop the older one"
+        );
+        assert!(receiver.reassembler.is_empty(), "should free the buffer");
+    }
+}
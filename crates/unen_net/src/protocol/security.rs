@@ -0,0 +1,553 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bincode::{Decode, Encode};
+use chacha20poly1305::{
+    aead::{Aead, AeadInPlace, Payload},
+    ChaCha20Poly1305, KeyInit, Tag, XChaCha20Poly1305,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::protocol::packet::PROTOCOL_ID;
+
+pub const KEY_SIZE: usize = 32;
+/// Bytes added to every encrypted packet: its sequence and the AEAD tag.
+pub const ENCRYPTION_OVERHEAD: usize = 8 + 16;
+/// How many sequences behind the newest one an encrypted packet may be before
+/// it is rejected as a possible replay.
+pub const REPLAY_WINDOW: usize = 256;
+
+pub const HANDSHAKE_NONCE_SIZE: usize = 16;
+pub const CHALLENGE_TAG_SIZE: usize = 16;
+
+pub type Key = [u8; KEY_SIZE];
+/// Random value each side contributes to the keys of a connection, so that
+/// no two connections encrypt with the same keys.
+pub type HandshakeNonce = [u8; HANDSHAKE_NONCE_SIZE];
+/// Proves a challenge comes from the server that opened the client's token,
+/// which is the only one besides the client knowing its keys.
+pub type ChallengeTag = [u8; CHALLENGE_TAG_SIZE];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to encrypt packet")]
+    EncryptionFailed,
+    #[error("failed to encode connect token: {0}")]
+    EncodeError(#[from] bincode::error::EncodeError),
+}
+
+/// Creates a random key, for instance the private key shared by a server and
+/// the service issuing its connect tokens.
+pub fn generate_key() -> Key {
+    rand::random()
+}
+
+pub fn generate_handshake_nonce() -> HandshakeNonce {
+    rand::random()
+}
+
+/// Part of a connect token only the server can read, sent by the client in
+/// its connect request.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SealedToken {
+    expires_at: u64,
+    nonce: [u8; 24],
+    data: Vec<u8>,
+}
+
+/// Content of a [`SealedToken`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct PrivateToken {
+    pub user_id: u64,
+    pub client_to_server_key: Key,
+    pub server_to_client_key: Key,
+}
+
+impl SealedToken {
+    /// Decrypts the token, unless it expired or was not sealed with `key`.
+    pub(crate) fn open(&self, key: &Key) -> Option<PrivateToken> {
+        if self.expires_at <= unix_time() {
+            return None;
+        }
+
+        let data = XChaCha20Poly1305::new(key.into())
+            .decrypt(
+                &self.nonce.into(),
+                Payload {
+                    msg: &self.data,
+                    aad: &token_aad(self.expires_at),
+                },
+            )
+            .ok()?;
+        bincode::decode_from_slice(&data, bincode::config::standard())
+            .ok()
+            .map(|(token, _)| token)
+    }
+
+    /// Random nonce of the token, identifying it.
+    pub(crate) fn nonce(&self) -> [u8; 24] {
+        self.nonce
+    }
+
+    pub(crate) fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+}
+
+impl PrivateToken {
+    /// Cipher of the server side of a connection, see
+    /// [`ConnectToken::client_cipher`].
+    pub(crate) fn server_cipher(
+        &self,
+        client_nonce: &HandshakeNonce,
+        server_nonce: &HandshakeNonce,
+    ) -> PacketCipher {
+        let (client_to_server, server_to_client) = connection_keys(
+            &self.client_to_server_key,
+            &self.server_to_client_key,
+            client_nonce,
+            server_nonce,
+        );
+        PacketCipher::new(&server_to_client, &client_to_server)
+    }
+
+    /// Tag of the challenge answering a connect request, see
+    /// [`ConnectToken::verify_challenge`].
+    pub(crate) fn challenge_tag(
+        &self,
+        client_nonce: &HandshakeNonce,
+        server_nonce: &HandshakeNonce,
+        challenge: u64,
+    ) -> ChallengeTag {
+        challenge_tag(
+            &self.server_to_client_key,
+            client_nonce,
+            server_nonce,
+            challenge,
+        )
+    }
+}
+
+/// Grants a client access to a secure server, along with the keys
+/// encrypting its connection.
+///
+/// Tokens are meant to be issued by a matchmaking service over a secure
+/// channel, see [`TokenIssuer`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ConnectToken {
+    sealed: SealedToken,
+    client_to_server_key: Key,
+    server_to_client_key: Key,
+}
+
+impl ConnectToken {
+    /// Seconds since the Unix epoch after which the server refuses the token.
+    pub fn expires_at(&self) -> u64 {
+        self.sealed.expires_at
+    }
+
+    pub(crate) fn sealed(&self) -> &SealedToken {
+        &self.sealed
+    }
+
+    /// Cipher of the client side of a connection, with keys derived from the
+    /// token keys and the nonces both sides picked for this connection.
+    pub(crate) fn client_cipher(
+        &self,
+        client_nonce: &HandshakeNonce,
+        server_nonce: &HandshakeNonce,
+    ) -> PacketCipher {
+        let (client_to_server, server_to_client) = connection_keys(
+            &self.client_to_server_key,
+            &self.server_to_client_key,
+            client_nonce,
+            server_nonce,
+        );
+        PacketCipher::new(&client_to_server, &server_to_client)
+    }
+
+    /// Whether a challenge was sent by the server the token is for, in
+    /// answer to the connect request carrying `client_nonce`.
+    pub(crate) fn verify_challenge(
+        &self,
+        client_nonce: &HandshakeNonce,
+        server_nonce: &HandshakeNonce,
+        challenge: u64,
+        tag: &ChallengeTag,
+    ) -> bool {
+        let expected = challenge_tag(
+            &self.server_to_client_key,
+            client_nonce,
+            server_nonce,
+            challenge,
+        );
+        // Compares every byte, so that timing does not tell how much matched
+        expected
+            .iter()
+            .zip(tag)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+/// Issues connect tokens for servers sharing its private key, standing in for
+/// a matchmaking service.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    private_key: Key,
+}
+
+impl TokenIssuer {
+    pub fn new(private_key: Key) -> Self {
+        Self { private_key }
+    }
+
+    /// Creates a token for the given user, valid for `valid_for`.
+    pub fn issue(&self, user_id: u64, valid_for: Duration) -> Result<ConnectToken, Error> {
+        let token = PrivateToken {
+            user_id,
+            client_to_server_key: generate_key(),
+            server_to_client_key: generate_key(),
+        };
+        let expires_at = unix_time() + valid_for.as_secs();
+        let nonce: [u8; 24] = rand::random();
+
+        let data = bincode::encode_to_vec(&token, bincode::config::standard())?;
+        let data = XChaCha20Poly1305::new((&self.private_key).into())
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: &data,
+                    aad: &token_aad(expires_at),
+                },
+            )
+            .map_err(|_| Error::EncryptionFailed)?;
+
+        Ok(ConnectToken {
+            sealed: SealedToken {
+                expires_at,
+                nonce,
+                data,
+            },
+            client_to_server_key: token.client_to_server_key,
+            server_to_client_key: token.server_to_client_key,
+        })
+    }
+}
+
+/// Encrypts and authenticates the packets of a connection in one direction
+/// and decrypts them in the other, rejecting replayed packets.
+#[derive(Clone)]
+pub(crate) struct PacketCipher {
+    send: ChaCha20Poly1305,
+    receive: ChaCha20Poly1305,
+    next_sequence: u64,
+    replay: ReplayWindow,
+}
+
+impl PacketCipher {
+    pub fn new(send_key: &Key, receive_key: &Key) -> Self {
+        Self {
+            send: ChaCha20Poly1305::new(send_key.into()),
+            receive: ChaCha20Poly1305::new(receive_key.into()),
+            next_sequence: 0,
+            replay: ReplayWindow::new(),
+        }
+    }
+
+    /// Encrypts the encoded packet in `buf` in place, prefixing it with its
+    /// sequence and appending the tag.
+    pub fn seal(&mut self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let tag = self
+            .send
+            .encrypt_in_place_detached(&nonce(sequence).into(), PROTOCOL_ID, buf)
+            .map_err(|_| Error::EncryptionFailed)?;
+        buf.reserve(ENCRYPTION_OVERHEAD);
+        buf.extend_from_slice(&tag);
+        buf.splice(0..0, sequence.to_le_bytes());
+        Ok(())
+    }
+
+    /// Decrypts a datagram in place, returning the packet it carries unless
+    /// it was tampered with or already received.
+    ///
+    /// The datagram is left untouched when it can not be authenticated.
+    pub fn open<'a>(&mut self, datagram: &'a mut [u8]) -> Option<&'a [u8]> {
+        if datagram.len() < ENCRYPTION_OVERHEAD {
+            return None;
+        }
+        let (sequence, rest) = datagram.split_first_chunk_mut::<8>()?;
+        let sequence = u64::from_le_bytes(*sequence);
+        if self.replay.is_replayed(sequence) {
+            return None;
+        }
+
+        let (packet, tag) = rest.split_at_mut(rest.len() - 16);
+        self.receive
+            .decrypt_in_place_detached(
+                &nonce(sequence).into(),
+                PROTOCOL_ID,
+                packet,
+                Tag::from_slice(tag),
+            )
+            .ok()?;
+        // Only authenticated sequences may move the window
+        self.replay.insert(sequence);
+        Some(packet)
+    }
+}
+
+/// Sequences of the recently received packets.
+#[derive(Debug, Clone)]
+struct ReplayWindow {
+    newest: u64,
+    /// Slot `sequence % REPLAY_WINDOW` holds the last sequence received
+    /// there, `u64::MAX` when empty.
+    received: Vec<u64>,
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self {
+            newest: 0,
+            received: vec![u64::MAX; REPLAY_WINDOW],
+        }
+    }
+
+    fn is_replayed(&self, sequence: u64) -> bool {
+        if sequence.saturating_add(REPLAY_WINDOW as u64) <= self.newest {
+            return true;
+        }
+
+        let slot = self.received[sequence as usize % REPLAY_WINDOW];
+        slot != u64::MAX && slot >= sequence
+    }
+
+    fn insert(&mut self, sequence: u64) {
+        self.newest = self.newest.max(sequence);
+        self.received[sequence as usize % REPLAY_WINDOW] = sequence;
+    }
+}
+
+/// Derives the keys of one connection from the keys of its token, since
+/// packet sequences start over on every connection.
+fn connection_keys(
+    client_to_server: &Key,
+    server_to_client: &Key,
+    client_nonce: &HandshakeNonce,
+    server_nonce: &HandshakeNonce,
+) -> (Key, Key) {
+    let mut salt = [0u8; 2 * HANDSHAKE_NONCE_SIZE];
+    salt[..HANDSHAKE_NONCE_SIZE].copy_from_slice(client_nonce);
+    salt[HANDSHAKE_NONCE_SIZE..].copy_from_slice(server_nonce);
+    let mut token_keys = [0u8; 2 * KEY_SIZE];
+    token_keys[..KEY_SIZE].copy_from_slice(client_to_server);
+    token_keys[KEY_SIZE..].copy_from_slice(server_to_client);
+
+    let mut keys = [0u8; 2 * KEY_SIZE];
+    Hkdf::<Sha256>::new(Some(&salt), &token_keys)
+        .expand(PROTOCOL_ID, &mut keys)
+        .expect("should fit the output of HKDF-SHA256");
+    let (client_to_server, server_to_client) = keys.split_at(KEY_SIZE);
+    (
+        client_to_server.try_into().unwrap(),
+        server_to_client.try_into().unwrap(),
+    )
+}
+
+/// HMAC-SHA256 of the challenge and both nonces, truncated.
+fn challenge_tag(
+    key: &Key,
+    client_nonce: &HandshakeNonce,
+    server_nonce: &HandshakeNonce,
+    challenge: u64,
+) -> ChallengeTag {
+    let mut message = PROTOCOL_ID.to_vec();
+    message.extend_from_slice(client_nonce);
+    message.extend_from_slice(server_nonce);
+    message.extend_from_slice(&challenge.to_le_bytes());
+
+    // HKDF-Extract is HMAC keyed with the salt
+    let (mac, _) = Hkdf::<Sha256>::extract(Some(key), &message);
+    mac[..CHALLENGE_TAG_SIZE].try_into().unwrap()
+}
+
+fn nonce(sequence: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce
+}
+
+fn token_aad(expires_at: u64) -> Vec<u8> {
+    let mut aad = PROTOCOL_ID.to_vec();
+    aad.extend_from_slice(&expires_at.to_le_bytes());
+    aad
+}
+
+/// Seconds since the Unix epoch, the clock connect tokens expire on.
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_token() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(7, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+
+        let opened = token
+            .sealed()
+            .open(&private_key)
+            .expect("server should be able to open the token");
+        assert_eq!(opened.user_id, 7, "should carry the user id");
+        assert_eq!(
+            opened.client_to_server_key, token.client_to_server_key,
+            "should carry the client keys"
+        );
+        assert!(
+            token.sealed().open(&generate_key()).is_none(),
+            "should not open with another key"
+        );
+
+        let mut forged = token.sealed().clone();
+        forged.expires_at += 60;
+        assert!(
+            forged.open(&private_key).is_none(),
+            "should not open once tampered with"
+        );
+
+        let expired = TokenIssuer::new(private_key)
+            .issue(7, Duration::ZERO)
+            .expect("should be able to issue a token");
+        assert!(
+            expired.sealed().open(&private_key).is_none(),
+            "should not open once expired"
+        );
+    }
+
+    #[test]
+    fn test_connection_keys() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(7, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let opened = token
+            .sealed()
+            .open(&private_key)
+            .expect("server should be able to open the token");
+        let (client_nonce, server_nonce) = (generate_handshake_nonce(), generate_handshake_nonce());
+
+        let mut client = token.client_cipher(&client_nonce, &server_nonce);
+        let mut server = opened.server_cipher(&client_nonce, &server_nonce);
+        let mut packet = b"hello".to_vec();
+        client.seal(&mut packet).expect("should be able to seal");
+        assert_eq!(
+            server.open(&mut packet.clone()),
+            Some(&b"hello"[..]),
+            "both sides should derive the same keys"
+        );
+
+        let mut other = opened.server_cipher(&client_nonce, &generate_handshake_nonce());
+        assert_eq!(
+            other.open(&mut packet),
+            None,
+            "other connections should use other keys"
+        );
+    }
+
+    #[test]
+    fn test_packet_cipher() {
+        let (a, b) = (generate_key(), generate_key());
+        let mut client = PacketCipher::new(&a, &b);
+        let mut server = PacketCipher::new(&b, &a);
+        let seal = |cipher: &mut PacketCipher, packet: &[u8]| {
+            let mut buf = packet.to_vec();
+            cipher.seal(&mut buf).expect("should be able to seal");
+            buf
+        };
+
+        let first = seal(&mut client, b"first");
+        let second = seal(&mut client, b"second");
+        assert_eq!(
+            server.open(&mut second.clone()),
+            Some(&b"second"[..]),
+            "should decrypt"
+        );
+        assert_eq!(
+            server.open(&mut first.clone()),
+            Some(&b"first"[..]),
+            "should accept reordered packets"
+        );
+        assert_eq!(
+            server.open(&mut first.clone()),
+            None,
+            "should reject replayed packets"
+        );
+
+        let mut tampered = seal(&mut client, b"third");
+        *tampered.last_mut().unwrap() ^= 1;
+        let original = tampered.clone();
+        assert_eq!(
+            server.open(&mut tampered),
+            None,
+            "should reject tampered packets"
+        );
+        assert_eq!(tampered, original, "should leave rejected datagrams intact");
+
+        for _ in 0..REPLAY_WINDOW {
+            server.open(&mut seal(&mut client, b""));
+        }
+        let mut late = seal(&mut PacketCipher::new(&a, &b), b"late");
+        assert_eq!(
+            server.open(&mut late),
+            None,
+            "should reject packets too old"
+        );
+    }
+
+    #[test]
+    fn test_challenge_tag() {
+        let private_key = generate_key();
+        let token = TokenIssuer::new(private_key)
+            .issue(7, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        let opened = token
+            .sealed()
+            .open(&private_key)
+            .expect("server should be able to open the token");
+        let (client_nonce, server_nonce) = (generate_handshake_nonce(), generate_handshake_nonce());
+
+        let tag = opened.challenge_tag(&client_nonce, &server_nonce, 42);
+        assert!(
+            token.verify_challenge(&client_nonce, &server_nonce, 42, &tag),
+            "should verify challenges of the server"
+        );
+        assert!(
+            !token.verify_challenge(&client_nonce, &generate_handshake_nonce(), 42, &tag),
+            "should refuse challenges with another nonce"
+        );
+        assert!(
+            !token.verify_challenge(&client_nonce, &server_nonce, 43, &tag),
+            "should refuse other challenges"
+        );
+        let other = TokenIssuer::new(private_key)
+            .issue(7, Duration::from_secs(30))
+            .expect("should be able to issue a token");
+        assert!(
+            !other.verify_challenge(&client_nonce, &server_nonce, 42, &tag),
+            "should refuse challenges for other tokens"
+        );
+    }
+}
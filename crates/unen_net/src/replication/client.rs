@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use crate::replication::{Error, Snapshot, SnapshotAck, SnapshotMessage, SNAPSHOT_HISTORY};
+
+/// Client side of replication, rebuilding the snapshots of the server from
+/// the changes it sends.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationClient {
+    /// Snapshots applied, by tick, kept as baselines of the next ones.
+    received: BTreeMap<u32, Snapshot>,
+    latest: Option<u32>,
+}
+
+impl ReplicationClient {
+    /// Applies a snapshot message, returning the snapshot it describes unless
+    /// a newer one was already applied.
+    ///
+    /// The server must then be sent [`ack`](Self::ack) so that it stops
+    /// resending what the client already has.
+    pub fn apply(&mut self, message: &SnapshotMessage) -> Result<Option<&Snapshot>, Error> {
+        let mut snapshot = match message.baseline {
+            Some(baseline) => self
+                .received
+                .get(&baseline)
+                .ok_or(Error::MissingBaseline(baseline))?
+                .clone(),
+            None => Snapshot::default(),
+        };
+        for &entity in &message.removed {
+            snapshot.remove(entity);
+        }
+        for delta in &message.entities {
+            let state = delta.apply(snapshot.get(delta.entity()))?;
+            snapshot.insert(delta.entity(), state);
+        }
+
+        // The server never goes back to a baseline older than one it used
+        if let Some(baseline) = message.baseline {
+            self.received.retain(|&tick, _| tick >= baseline);
+        }
+        self.received.insert(message.tick, snapshot);
+        while self.received.len() > SNAPSHOT_HISTORY {
+            self.received.pop_first();
+        }
+
+        if self.latest.is_some_and(|latest| latest >= message.tick) {
+            return Ok(None);
+        }
+        self.latest = Some(message.tick);
+        Ok(self.received.get(&message.tick))
+    }
+
+    /// Newest snapshot applied, along with its tick.
+    pub fn latest(&self) -> Option<(u32, &Snapshot)> {
+        let tick = self.latest?;
+        self.received.get(&tick).map(|snapshot| (tick, snapshot))
+    }
+
+    /// Acknowledgement of the newest snapshot applied.
+    pub fn ack(&self) -> Option<SnapshotAck> {
+        self.latest.map(|tick| SnapshotAck { tick })
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bincode::Decode;
+
+use crate::replication::{NetEntity, Snapshot};
+
+/// Default delay of the rendered state behind the newest snapshot, two
+/// snapshots at 20 Hz.
+pub const DEFAULT_INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+/// Default time the state may be extrapolated past the newest snapshot.
+pub const DEFAULT_MAX_EXTRAPOLATION: Duration = Duration::from_millis(50);
+/// Default amount of snapshots buffered.
+pub const DEFAULT_BUFFER_CAPACITY: usize = 32;
+
+/// How fast the estimated server clock follows snapshots arriving later
+/// than expected.
+const CLOCK_SMOOTHING: f64 = 0.05;
+
+/// A value that can be blended between two snapshots.
+pub trait Interpolate {
+    /// Blends from `self` at `alpha` 0 to `other` at 1, going past `other`
+    /// when extrapolating.
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        self + (other - self) * alpha
+    }
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        self + (other - self) * alpha as f64
+    }
+}
+
+impl<T: Interpolate, const N: usize> Interpolate for [T; N] {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        std::array::from_fn(|i| self[i].interpolate(&other[i], alpha))
+    }
+}
+
+/// Counters of an [`InterpolationBuffer`], telling whether its delay suits
+/// the network conditions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterpolationStats {
+    /// Samples taken past the newest snapshot further than the
+    /// extrapolation limit, the delay is too short.
+    pub underruns: u64,
+    /// Samples taken past the newest snapshot within the extrapolation
+    /// limit.
+    pub extrapolations: u64,
+    /// Snapshots received after the rendered state already went past them.
+    pub late_snapshots: u64,
+}
+
+/// State between two snapshots at the sampled time.
+#[derive(Debug, Clone, Copy)]
+pub struct InterpolationSample<'a> {
+    pub from: &'a Snapshot,
+    pub to: &'a Snapshot,
+    /// Position between the snapshots, above 1 when extrapolating.
+    pub alpha: f32,
+}
+
+impl InterpolationSample<'_> {
+    /// Blends a field of an entity, or takes it from the only snapshot
+    /// holding the entity.
+    pub fn field<T: Decode<()> + Interpolate>(&self, entity: NetEntity, index: usize) -> Option<T> {
+        let from = self
+            .from
+            .get(entity)
+            .and_then(|state| state.field::<T>(index).ok());
+        let to = self
+            .to
+            .get(entity)
+            .and_then(|state| state.field::<T>(index).ok());
+        match (from, to) {
+            (Some(from), Some(to)) => Some(from.interpolate(&to, self.alpha)),
+            (from, to) => to.or(from),
+        }
+    }
+}
+
+/// Jitter buffer of the snapshots received by a client, rendering them
+/// `interpolation_delay` in the past so that there is usually a snapshot on
+/// each side of the rendered time.
+#[derive(Debug, Clone)]
+pub struct InterpolationBuffer {
+    tick_interval: Duration,
+    interpolation_delay: Duration,
+    max_extrapolation: Duration,
+    capacity: usize,
+    /// Buffered snapshots, ordered by tick.
+    snapshots: VecDeque<(u32, Snapshot)>,
+    /// Estimated local time of server tick 0.
+    clock: Option<Instant>,
+    stats: InterpolationStats,
+}
+
+impl InterpolationBuffer {
+    /// Creates a buffer for snapshots sent every `tick_interval`.
+    pub fn new(tick_interval: Duration) -> Self {
+        Self {
+            tick_interval,
+            interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
+            max_extrapolation: DEFAULT_MAX_EXTRAPOLATION,
+            capacity: DEFAULT_BUFFER_CAPACITY,
+            snapshots: VecDeque::new(),
+            clock: None,
+            stats: InterpolationStats::default(),
+        }
+    }
+
+    /// Sets how far behind the newest snapshot the state is rendered,
+    /// trading latency for resilience to jitter and loss.
+    pub fn with_interpolation_delay(mut self, interpolation_delay: Duration) -> Self {
+        self.interpolation_delay = interpolation_delay;
+        self
+    }
+
+    /// Sets how far past the newest snapshot the state may be extrapolated
+    /// when the next one is late, after which it freezes.
+    pub fn with_max_extrapolation(mut self, max_extrapolation: Duration) -> Self {
+        self.max_extrapolation = max_extrapolation;
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Buffers a snapshot applied by a
+    /// [`ReplicationClient`](crate::replication::ReplicationClient).
+    pub fn push(&mut self, tick: u32, snapshot: Snapshot, now: Instant) {
+        let sent_at = self.tick_interval.mul_f64(tick as f64);
+        let clock = now.checked_sub(sent_at).unwrap_or(now);
+        // Snapshots arriving early mean less latency than estimated, late
+        // ones are mostly jitter and only move the clock slowly
+        self.clock = Some(match self.clock {
+            Some(current) if clock < current => clock,
+            Some(current) => current + (clock - current).mul_f64(CLOCK_SMOOTHING),
+            None => clock,
+        });
+
+        if self
+            .render_tick(now)
+            .is_some_and(|render| (tick as f64) < render)
+        {
+            self.stats.late_snapshots += 1;
+        }
+        match self
+            .snapshots
+            .binary_search_by_key(&tick, |(tick, _)| *tick)
+        {
+            Ok(_) => {}
+            Err(index) => self.snapshots.insert(index, (tick, snapshot)),
+        }
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// State at `now - interpolation_delay`, none until a snapshot arrived.
+    pub fn sample(&mut self, now: Instant) -> Option<InterpolationSample<'_>> {
+        let render = self.render_tick(now)?;
+
+        // Keep the snapshot right before the rendered time, the older ones
+        // are not needed anymore, and the last two to extrapolate from
+        while self.snapshots.len() > 2 && self.snapshots[1].0 as f64 <= render {
+            self.snapshots.pop_front();
+        }
+
+        let (newest, _) = self.snapshots.back()?;
+        let past_newest = render - *newest as f64;
+        let limit = self.max_extrapolation.as_secs_f64() / self.tick_interval.as_secs_f64();
+        if past_newest > 0.0 {
+            if past_newest > limit {
+                self.stats.underruns += 1;
+            } else {
+                self.stats.extrapolations += 1;
+            }
+        }
+
+        let from = &self.snapshots[0];
+        let to = self.snapshots.get(1).unwrap_or(from);
+        let alpha = if to.0 == from.0 {
+            0.0
+        } else {
+            let span = (to.0 - from.0) as f64;
+            ((render - from.0 as f64) / span).clamp(0.0, 1.0 + limit / span) as f32
+        };
+
+        Some(InterpolationSample {
+            from: &from.1,
+            to: &to.1,
+            alpha,
+        })
+    }
+
+    pub fn stats(&self) -> InterpolationStats {
+        self.stats
+    }
+
+    /// Amount of snapshots buffered.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Server tick rendered at `now`, fractional between snapshots.
+    fn render_tick(&self, now: Instant) -> Option<f64> {
+        let elapsed = now.checked_duration_since(self.clock?)?;
+        let render = elapsed.as_secs_f64() - self.interpolation_delay.as_secs_f64();
+        Some(render / self.tick_interval.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::EntityState;
+
+    const TICK: Duration = Duration::from_millis(50);
+    const ENTITY: NetEntity = NetEntity::new(0);
+
+    fn snapshot(x: f32) -> Snapshot {
+        let mut state = EntityState::new();
+        state.push(&x).expect("should encode");
+        let mut snapshot = Snapshot::default();
+        snapshot.insert(ENTITY, state);
+        snapshot
+    }
+
+    fn x(buffer: &mut InterpolationBuffer, now: Instant) -> f32 {
+        buffer
+            .sample(now)
+            .and_then(|sample| sample.field::<f32>(ENTITY, 0))
+            .expect("should have a sample")
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut buffer = InterpolationBuffer::new(TICK);
+        let start = Instant::now();
+        for tick in 0..4 {
+            buffer.push(tick, snapshot(tick as f32 * 10.0), start + TICK * tick);
+        }
+
+        // Rendered 100ms, two ticks, in the past
+        let now = start + TICK * 3 + TICK / 2;
+        assert!(
+            (x(&mut buffer, now) - 15.0).abs() < 0.01,
+            "should interpolate between snapshots"
+        );
+        assert_eq!(
+            buffer.stats(),
+            InterpolationStats::default(),
+            "should not underrun"
+        );
+    }
+
+    #[test]
+    fn test_extrapolation_and_underruns() {
+        let mut buffer = InterpolationBuffer::new(TICK);
+        let start = Instant::now();
+        for tick in 0..2 {
+            buffer.push(tick, snapshot(tick as f32 * 10.0), start + TICK * tick);
+        }
+
+        // Renders tick 1.5 while the newest is 1
+        assert!(
+            (x(&mut buffer, start + TICK * 3 + TICK / 2) - 15.0).abs() < 0.01,
+            "should extrapolate past the newest snapshot"
+        );
+        assert_eq!(
+            buffer.stats().extrapolations,
+            1,
+            "should count extrapolations"
+        );
+
+        assert!(
+            (x(&mut buffer, start + TICK * 10) - 20.0).abs() < 0.01,
+            "should stop extrapolating at the limit"
+        );
+        assert_eq!(buffer.stats().underruns, 1, "should count underruns");
+
+        buffer.push(1, snapshot(10.0), start + TICK * 10);
+        assert_eq!(
+            buffer.stats().late_snapshots,
+            1,
+            "should count late snapshots"
+        );
+        assert_eq!(buffer.len(), 2, "should ignore duplicated snapshots");
+    }
+}
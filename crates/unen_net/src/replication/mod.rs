@@ -0,0 +1,43 @@
+use thiserror::Error;
+
+use crate::{
+    message::MESSAGE_ID_SIZE,
+    protocol::{encoding, reliability::FRAGMENT_SIZE},
+};
+
+mod client;
+mod interpolation;
+mod server;
+mod snapshot;
+
+pub use client::ReplicationClient;
+pub use interpolation::{
+    Interpolate, InterpolationBuffer, InterpolationSample, InterpolationStats,
+    DEFAULT_BUFFER_CAPACITY, DEFAULT_INTERPOLATION_DELAY, DEFAULT_MAX_EXTRAPOLATION,
+};
+pub use server::ReplicationServer;
+pub use snapshot::{
+    EntityDelta, EntityState, NetEntity, Snapshot, SnapshotAck, SnapshotMessage, MAX_FIELDS,
+};
+
+/// Default amount of bytes the entities of a snapshot may take, so that it
+/// is sent in one packet, with room for its entity count to grow.
+pub const DEFAULT_MAX_SNAPSHOT_SIZE: usize = FRAGMENT_SIZE - MESSAGE_ID_SIZE - 4;
+/// How many snapshots are kept per client to serve as baselines.
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("entity state can not have {0} fields")]
+    TooManyFields(usize),
+    #[error("entity {0} takes {1} bytes, more than a snapshot may hold")]
+    EntityTooLarge(NetEntity, usize),
+    #[error("entity state has no field {0}")]
+    MissingField(usize),
+    #[error("baseline snapshot of tick {0} is not known anymore")]
+    MissingBaseline(u32),
+    #[error("delta of entity {0} does not match its baseline")]
+    MalformedDelta(NetEntity),
+    #[error("encoding error: {0}")]
+    EncodingError(#[from] encoding::Error),
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    replication::{
+        snapshot::encoded_size, EntityState, Error, NetEntity, Snapshot, SnapshotMessage,
+        DEFAULT_MAX_SNAPSHOT_SIZE, SNAPSHOT_HISTORY,
+    },
+    server::ClientId,
+};
+
+type Relevancy = Box<dyn Fn(ClientId, NetEntity) -> bool + Send + Sync>;
+
+struct Replicated {
+    state: EntityState,
+    priority: f32,
+}
+
+/// What the server knows a client holds.
+#[derive(Default)]
+struct ClientView {
+    /// State the client holds once it applied each snapshot sent, by tick.
+    sent: BTreeMap<u32, Snapshot>,
+    /// Tick of the newest snapshot the client acknowledged.
+    acked: Option<u32>,
+    /// Priority accumulated by the changed entities left out of snapshots.
+    priorities: HashMap<NetEntity, f32>,
+}
+
+impl ClientView {
+    fn baseline(&self) -> Option<(u32, &Snapshot)> {
+        let tick = self.acked?;
+        self.sent.get(&tick).map(|snapshot| (tick, snapshot))
+    }
+}
+
+/// Server side of replication, writing for each client the changes of the
+/// replicated entities since the last snapshot it acknowledged.
+///
+/// Entities that do not fit a snapshot accumulate priority, so that every
+/// change eventually gets sent even when bandwidth is short.
+pub struct ReplicationServer {
+    entities: BTreeMap<NetEntity, Replicated>,
+    clients: HashMap<ClientId, ClientView>,
+    max_snapshot_size: usize,
+    relevancy: Option<Relevancy>,
+}
+
+impl ReplicationServer {
+    /// Sets how many bytes the entities of a snapshot may take, which keeps
+    /// the message in a single packet by default.
+    pub fn with_max_snapshot_size(mut self, max_snapshot_size: usize) -> Self {
+        self.max_snapshot_size = max_snapshot_size;
+        self
+    }
+
+    /// Only replicates the entities for which `relevancy` is true to a
+    /// client, the other ones are removed from its view.
+    pub fn with_relevancy(
+        mut self,
+        relevancy: impl Fn(ClientId, NetEntity) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.relevancy = Some(Box::new(relevancy));
+        self
+    }
+
+    /// Sets the state of an entity, replicating it from the next snapshot.
+    ///
+    /// States that could never fit a snapshot are rejected, the previous one
+    /// is kept.
+    pub fn set(&mut self, entity: NetEntity, state: EntityState) -> Result<(), Error> {
+        let empty = SnapshotMessage {
+            tick: u32::MAX,
+            baseline: Some(u32::MAX),
+            removed: Vec::new(),
+            entities: Vec::new(),
+        };
+        let size = state
+            .delta(entity, None)
+            .map_or(0, |delta| encoded_size(&delta));
+        if encoded_size(&empty) + size > self.max_snapshot_size {
+            return Err(Error::EntityTooLarge(entity, size));
+        }
+
+        self.entities
+            .entry(entity)
+            .and_modify(|replicated| replicated.state = state.clone())
+            .or_insert(Replicated {
+                state,
+                priority: 1.0,
+            });
+        Ok(())
+    }
+
+    /// Sets how fast the changes of an entity left out of snapshots gain
+    /// priority over the others, `1.0` by default.
+    pub fn set_priority(&mut self, entity: NetEntity, priority: f32) {
+        if let Some(replicated) = self.entities.get_mut(&entity) {
+            replicated.priority = priority;
+        }
+    }
+
+    pub fn remove(&mut self, entity: NetEntity) -> Option<EntityState> {
+        self.entities
+            .remove(&entity)
+            .map(|replicated| replicated.state)
+    }
+
+    pub fn get(&self, entity: NetEntity) -> Option<&EntityState> {
+        self.entities
+            .get(&entity)
+            .map(|replicated| &replicated.state)
+    }
+
+    /// Forgets what was sent to a client, typically once it disconnected.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    /// Marks a snapshot as received by a client, the next ones only carry
+    /// the changes since.
+    pub fn acknowledge(&mut self, client: ClientId, tick: u32) {
+        let Some(view) = self.clients.get_mut(&client) else {
+            return;
+        };
+        if !view.sent.contains_key(&tick) || view.acked.is_some_and(|acked| acked >= tick) {
+            return;
+        }
+
+        view.acked = Some(tick);
+        view.sent.retain(|&sent, _| sent >= tick);
+    }
+
+    /// Writes the snapshot of a tick for a client, ticks must increase.
+    pub fn write_snapshot(&mut self, client: ClientId, tick: u32) -> SnapshotMessage {
+        let view = self.clients.entry(client).or_default();
+        let (baseline_tick, baseline) = match view.baseline() {
+            Some((tick, snapshot)) => (Some(tick), snapshot.clone()),
+            None => (None, Snapshot::default()),
+        };
+
+        let relevant = |entity: NetEntity| {
+            self.relevancy
+                .as_ref()
+                .is_none_or(|relevancy| relevancy(client, entity))
+        };
+        let mut message = SnapshotMessage {
+            tick,
+            baseline: baseline_tick,
+            removed: Vec::new(),
+            entities: Vec::new(),
+        };
+        let mut size = encoded_size(&message);
+
+        // Removals share the size budget, the ones left out stay in the
+        // snapshot and are sent with the next one
+        let removed = baseline
+            .iter()
+            .map(|(entity, _)| entity)
+            .filter(|&entity| !self.entities.contains_key(&entity) || !relevant(entity));
+        for entity in removed {
+            let count = message.removed.len();
+            let removal_size =
+                encoded_size(&entity) + encoded_size(&(count + 1)) - encoded_size(&count);
+            if size + removal_size > self.max_snapshot_size {
+                break;
+            }
+
+            size += removal_size;
+            message.removed.push(entity);
+        }
+
+        let mut changed: Vec<_> = self
+            .entities
+            .iter()
+            .filter(|(&entity, _)| relevant(entity))
+            .filter_map(|(&entity, replicated)| {
+                let delta = replicated.state.delta(entity, baseline.get(entity))?;
+                let priority = view.priorities.entry(entity).or_default();
+                *priority += replicated.priority;
+                Some((*priority, delta, &replicated.state))
+            })
+            .collect();
+        changed.sort_by(|(a, ..), (b, ..)| b.total_cmp(a));
+
+        let mut snapshot = baseline;
+        for &entity in &message.removed {
+            snapshot.remove(entity);
+            view.priorities.remove(&entity);
+        }
+        for (_, delta, state) in changed {
+            let delta_size = encoded_size(&delta);
+            if size + delta_size > self.max_snapshot_size {
+                continue;
+            }
+
+            size += delta_size;
+            snapshot.insert(delta.entity(), state.clone());
+            view.priorities.remove(&delta.entity());
+            message.entities.push(delta);
+        }
+
+        view.sent.insert(tick, snapshot);
+        while view.sent.len() > SNAPSHOT_HISTORY {
+            view.sent.pop_first();
+        }
+        message
+    }
+}
+
+impl Default for ReplicationServer {
+    fn default() -> Self {
+        Self {
+            entities: BTreeMap::new(),
+            clients: HashMap::new(),
+            max_snapshot_size: DEFAULT_MAX_SNAPSHOT_SIZE,
+            relevancy: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::{ReplicationClient, SnapshotMessage};
+
+    const CLIENT: ClientId = ClientId::new(0);
+
+    fn state(x: i32, padding: usize) -> EntityState {
+        let mut state = EntityState::new();
+        state.push(&x).expect("should encode");
+        state.push(&vec![0u8; padding]).expect("should encode");
+        state
+    }
+
+    fn entities(message: &SnapshotMessage) -> Vec<u32> {
+        message
+            .entities
+            .iter()
+            .map(|delta| delta.entity().raw())
+            .collect()
+    }
+
+    #[test]
+    fn test_baseline_and_ack() {
+        let mut server = ReplicationServer::default();
+        let mut client = ReplicationClient::default();
+        server.set(NetEntity::new(1), state(0, 8)).unwrap();
+        server.set(NetEntity::new(2), state(0, 8)).unwrap();
+
+        let first = server.write_snapshot(CLIENT, 1);
+        assert_eq!(first.baseline, None, "should start with a full snapshot");
+        client.apply(&first).expect("should apply");
+        let unacked = server.write_snapshot(CLIENT, 2);
+        assert_eq!(
+            entities(&unacked),
+            vec![1, 2],
+            "should resend everything until acknowledged"
+        );
+
+        server.acknowledge(CLIENT, client.ack().expect("should ack").tick);
+        server.set(NetEntity::new(2), state(7, 8)).unwrap();
+        server.remove(NetEntity::new(1));
+        let delta = server.write_snapshot(CLIENT, 3);
+        assert_eq!(
+            delta.baseline,
+            Some(1),
+            "should use the acknowledged snapshot"
+        );
+        assert_eq!(
+            delta.removed,
+            vec![NetEntity::new(1)],
+            "should send removals"
+        );
+        assert_eq!(
+            entities(&delta),
+            vec![2],
+            "should only send changed entities"
+        );
+        assert_eq!(
+            delta.entities[0].changed().collect::<Vec<_>>(),
+            vec![0],
+            "should only send changed fields"
+        );
+
+        let snapshot = client
+            .apply(&delta)
+            .expect("should apply")
+            .expect("should be the newest snapshot");
+        assert_eq!(snapshot.len(), 1, "should remove entities");
+        assert_eq!(
+            snapshot
+                .get(NetEntity::new(2))
+                .unwrap()
+                .field::<i32>(0)
+                .unwrap(),
+            7,
+            "should apply changes"
+        );
+        assert!(
+            client.apply(&unacked).expect("should apply").is_none(),
+            "should not return older snapshots"
+        );
+    }
+
+    #[test]
+    fn test_priority_and_relevancy() {
+        let mut server =
+            ReplicationServer::default().with_relevancy(|_, entity| entity.raw() != 99);
+        let mut client = ReplicationClient::default();
+        for id in 0..20 {
+            server
+                .set(NetEntity::new(id), state(id as i32, 200))
+                .unwrap();
+        }
+        server.set_priority(NetEntity::new(19), 10.0);
+        server.set(NetEntity::new(99), state(0, 0)).unwrap();
+
+        let mut received = Vec::new();
+        for tick in 0..10 {
+            let message = server.write_snapshot(CLIENT, tick);
+            assert!(
+                encoded_size(&message) <= DEFAULT_MAX_SNAPSHOT_SIZE,
+                "should fit the size budget"
+            );
+            if tick == 0 {
+                assert_eq!(
+                    message.entities[0].entity(),
+                    NetEntity::new(19),
+                    "should send high priority entities first"
+                );
+            }
+
+            received.extend(entities(&message));
+            client.apply(&message).expect("should apply");
+            server.acknowledge(CLIENT, tick);
+        }
+
+        received.sort();
+        received.dedup();
+        assert_eq!(
+            received,
+            (0..20).collect::<Vec<_>>(),
+            "should eventually send every relevant entity"
+        );
+        let (_, snapshot) = client.latest().expect("should have a snapshot");
+        assert_eq!(snapshot.len(), 20, "should hold every relevant entity");
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut server = ReplicationServer::default().with_max_snapshot_size(64);
+        let mut client = ReplicationClient::default();
+        assert!(
+            matches!(
+                server.set(NetEntity::new(0), state(0, 64)),
+                Err(Error::EntityTooLarge(..))
+            ),
+            "should reject states that can not fit a snapshot"
+        );
+        assert!(
+            server.get(NetEntity::new(0)).is_none(),
+            "should not keep rejected states"
+        );
+
+        for id in 0..40 {
+            server.set(NetEntity::new(id), state(0, 0)).unwrap();
+        }
+        let mut tick = 0;
+        while client
+            .latest()
+            .is_none_or(|(_, snapshot)| snapshot.len() < 40)
+        {
+            let message = server.write_snapshot(CLIENT, tick);
+            client.apply(&message).expect("should apply");
+            server.acknowledge(CLIENT, tick);
+            tick += 1;
+        }
+
+        for id in 0..40 {
+            server.remove(NetEntity::new(id));
+        }
+        let mut removed = Vec::new();
+        for _ in 0..40 {
+            let message = server.write_snapshot(CLIENT, tick);
+            assert!(
+                encoded_size(&message) <= 64,
+                "should fit removals in the size budget"
+            );
+            removed.extend(message.removed.iter().map(|entity| entity.raw()));
+            client.apply(&message).expect("should apply");
+            server.acknowledge(CLIENT, tick);
+            tick += 1;
+        }
+        assert_eq!(
+            removed,
+            (0..40).collect::<Vec<_>>(),
+            "should eventually send every removal once"
+        );
+        let (_, snapshot) = client.latest().expect("should have a snapshot");
+        assert!(snapshot.is_empty(), "should remove every entity");
+    }
+}
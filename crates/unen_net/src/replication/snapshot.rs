@@ -0,0 +1,289 @@
+use std::{collections::BTreeMap, fmt};
+
+use bincode::{enc::write::SizeWriter, Decode, Encode};
+
+use crate::{
+    message::{MessageId, NetMessage},
+    protocol::encoding::{decode_from_vec, encode_to_vec},
+    replication::Error,
+};
+
+/// Most fields an entity state may have, one bit each in a delta.
+pub const MAX_FIELDS: usize = 64;
+
+/// Identifier of a replicated entity, the same on the server and every
+/// client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Encode, Decode)]
+pub struct NetEntity(u32);
+
+impl NetEntity {
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for NetEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Replicated state of an entity, as a list of separately encoded fields so
+/// that only the changed ones are sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct EntityState {
+    fields: Vec<Vec<u8>>,
+}
+
+impl EntityState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a field, usually one per replicated component.
+    pub fn push<T: Encode>(&mut self, value: &T) -> Result<(), Error> {
+        if self.fields.len() >= MAX_FIELDS {
+            return Err(Error::TooManyFields(self.fields.len() + 1));
+        }
+
+        self.fields.push(encode_to_vec(value)?);
+        Ok(())
+    }
+
+    pub fn field<T: Decode<()>>(&self, index: usize) -> Result<T, Error> {
+        let field = self.fields.get(index).ok_or(Error::MissingField(index))?;
+        Ok(decode_from_vec(field)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Fields changed since `baseline`, every field without one.
+    pub(crate) fn delta(
+        &self,
+        entity: NetEntity,
+        baseline: Option<&EntityState>,
+    ) -> Option<EntityDelta> {
+        let mut changed = 0u64;
+        let mut fields = Vec::new();
+        for (index, field) in self.fields.iter().enumerate() {
+            if baseline.and_then(|baseline| baseline.fields.get(index)) != Some(field) {
+                changed |= 1 << index;
+                fields.push(field.clone());
+            }
+        }
+
+        let resized = baseline.is_none_or(|baseline| baseline.len() != self.len());
+        (changed != 0 || resized).then_some(EntityDelta {
+            entity,
+            field_count: self.fields.len() as u8,
+            changed,
+            fields,
+        })
+    }
+}
+
+/// Changed fields of an entity relative to a baseline state.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct EntityDelta {
+    entity: NetEntity,
+    field_count: u8,
+    /// Bit `i` is set when field `i` changed.
+    changed: u64,
+    fields: Vec<Vec<u8>>,
+}
+
+impl EntityDelta {
+    pub fn entity(&self) -> NetEntity {
+        self.entity
+    }
+
+    /// Indices of the fields the delta carries.
+    pub fn changed(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_FIELDS).filter(|index| self.changed & (1 << index) != 0)
+    }
+
+    /// Applies the delta to the baseline state of its entity, if any.
+    pub(crate) fn apply(&self, baseline: Option<&EntityState>) -> Result<EntityState, Error> {
+        let field_count = self.field_count as usize;
+        // Changed bits past the field count would otherwise be ignored, and
+        // a bad count resize the state beyond what `push` allows
+        if field_count > MAX_FIELDS
+            || self.changed.checked_shr(field_count as u32).unwrap_or(0) != 0
+            || self.changed.count_ones() as usize != self.fields.len()
+        {
+            return Err(Error::MalformedDelta(self.entity));
+        }
+
+        let mut state = baseline.cloned().unwrap_or_default();
+        state.fields.resize(field_count, Vec::new());
+
+        let mut changed = self.fields.iter();
+        for (index, field) in state.fields.iter_mut().enumerate() {
+            if self.changed & (1 << index) != 0 {
+                if let Some(changed) = changed.next() {
+                    field.clone_from(changed);
+                }
+            } else if baseline.is_none_or(|baseline| index >= baseline.len()) {
+                return Err(Error::MalformedDelta(self.entity));
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// State of every entity a client knows about at some tick.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    entities: BTreeMap<NetEntity, EntityState>,
+}
+
+impl Snapshot {
+    pub fn get(&self, entity: NetEntity) -> Option<&EntityState> {
+        self.entities.get(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NetEntity, &EntityState)> {
+        self.entities.iter().map(|(&entity, state)| (entity, state))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub(crate) fn insert(&mut self, entity: NetEntity, state: EntityState) {
+        self.entities.insert(entity, state);
+    }
+
+    pub(crate) fn remove(&mut self, entity: NetEntity) {
+        self.entities.remove(&entity);
+    }
+}
+
+/// Snapshot sent by the server, as changes relative to the last one the
+/// client acknowledged.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SnapshotMessage {
+    pub tick: u32,
+    /// Tick of the snapshot the changes apply to, none for a full snapshot.
+    pub baseline: Option<u32>,
+    pub removed: Vec<NetEntity>,
+    pub entities: Vec<EntityDelta>,
+}
+
+impl NetMessage for SnapshotMessage {
+    const ID: MessageId = MessageId::new(0x756e_0001);
+}
+
+/// Sent back by clients for every snapshot applied, it becomes the baseline
+/// of the next ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct SnapshotAck {
+    pub tick: u32,
+}
+
+impl NetMessage for SnapshotAck {
+    const ID: MessageId = MessageId::new(0x756e_0002);
+}
+
+/// Encoded size of a value, without encoding it anywhere.
+pub(crate) fn encoded_size<T: Encode>(value: &T) -> usize {
+    let mut writer = SizeWriter::default();
+    match bincode::encode_into_writer(value, &mut writer, bincode::config::standard()) {
+        Ok(()) => writer.bytes_written,
+        Err(_) => usize::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(position: (i32, i32), health: u8) -> EntityState {
+        let mut state = EntityState::new();
+        state.push(&position).expect("should encode");
+        state.push(&health).expect("should encode");
+        state
+    }
+
+    #[test]
+    fn test_delta() {
+        let entity = NetEntity::new(1);
+        let baseline = state((0, 0), 100);
+        let moved = state((5, 0), 100);
+
+        let delta = moved
+            .delta(entity, Some(&baseline))
+            .expect("should have a delta");
+        assert_eq!(delta.fields.len(), 1, "should only carry the changed field");
+        assert_eq!(
+            delta.apply(Some(&baseline)).expect("should apply"),
+            moved,
+            "should rebuild the state from the baseline"
+        );
+        assert!(
+            moved.delta(entity, Some(&moved)).is_none(),
+            "should not have a delta without changes"
+        );
+
+        let full = moved.delta(entity, None).expect("should have a delta");
+        assert_eq!(full.fields.len(), 2, "should carry every field");
+        assert_eq!(
+            full.apply(None)
+                .expect("should apply")
+                .field::<(i32, i32)>(0)
+                .unwrap(),
+            (5, 0),
+            "should rebuild the state without baseline"
+        );
+        assert!(
+            delta.apply(None).is_err(),
+            "should reject partial deltas without baseline"
+        );
+    }
+
+    #[test]
+    fn test_malformed_delta() {
+        let entity = NetEntity::new(1);
+        let baseline = state((0, 0), 100);
+        let delta = |field_count, changed, fields: usize| EntityDelta {
+            entity,
+            field_count,
+            changed,
+            fields: vec![Vec::new(); fields],
+        };
+
+        for (malformed, reason) in [
+            (delta(200, 0, 0), "more fields than allowed"),
+            (delta(2, 0b100, 1), "changes past the field count"),
+            (delta(2, 1 << 63, 1), "changes past the last field"),
+            (delta(2, 0b01, 0), "missing fields"),
+            (delta(2, 0b01, 2), "extra fields"),
+        ] {
+            assert!(
+                matches!(
+                    malformed.apply(Some(&baseline)),
+                    Err(Error::MalformedDelta(_))
+                ),
+                "should reject deltas with {reason}"
+            );
+        }
+        assert!(
+            delta(64, 0, 0).apply(None).is_err(),
+            "should reject unchanged fields without baseline"
+        );
+    }
+}
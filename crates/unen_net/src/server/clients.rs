@@ -0,0 +1,110 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connection::{Connection, ConnectionConfig},
+    stats::NetworkStats,
+};
+
+/// Stable identifier of a client connected to a server.
+///
+/// Ids are never reused during the lifetime of a server, so an id held after
+/// its client left can not accidentally address a newer client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(u64);
+
+impl ClientId {
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Server-side state of a connected client.
+#[derive(Debug, Clone)]
+pub struct ClientEntry {
+    id: ClientId,
+    addr: SocketAddr,
+    version: u16,
+    user_id: Option<u64>,
+    connected_at: Instant,
+    connection: Connection,
+}
+
+impl ClientEntry {
+    pub(crate) fn new(
+        id: ClientId,
+        addr: SocketAddr,
+        version: u16,
+        user_id: Option<u64>,
+        config: ConnectionConfig,
+        now: Instant,
+    ) -> Self {
+        Self {
+            id,
+            addr,
+            version,
+            user_id,
+            connected_at: now,
+            connection: Connection::new(config, now),
+        }
+    }
+
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Protocol version agreed on during the handshake.
+    pub fn protocol_version(&self) -> u16 {
+        self.version
+    }
+
+    /// User the connect token of the client was issued for, on secure
+    /// servers.
+    pub fn user_id(&self) -> Option<u64> {
+        self.user_id
+    }
+
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    /// Last time a packet was received from the client.
+    pub fn last_seen(&self) -> Instant {
+        self.connection.last_received()
+    }
+
+    /// Time elapsed since the last packet was received from the client.
+    pub fn idle_time(&self) -> Duration {
+        self.last_seen().elapsed()
+    }
+
+    /// Round-trip time, packet loss and bandwidth of the connection.
+    pub fn stats(&self) -> NetworkStats {
+        self.connection.stats(Instant::now())
+    }
+
+    pub(crate) fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    pub(crate) fn connection_mut(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+}
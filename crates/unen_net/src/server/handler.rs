@@ -0,0 +1,181 @@
+use std::{any::Any, sync::Mutex};
+
+use unen_event::prelude::{EngineEvent, Event, EventBox, EventEmitter, EventHandler};
+
+use crate::{
+    connection::DisconnectReason,
+    message::{AnyMessage, NetMessage},
+    protocol::channel::ChannelId,
+    server::{ClientId, Error, ListeningServer, ServerEvent},
+};
+
+/// Connection changes of a [`NetworkServerHandler`], emitted on the engine
+/// bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkServerEvent {
+    ClientConnected(ClientId),
+    ClientDisconnected(ClientId, DisconnectReason),
+}
+
+impl Event for NetworkServerEvent {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Clients a [`SendToClient`] message is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recipient {
+    Client(ClientId),
+    /// Every connected client.
+    All,
+}
+
+/// Event asking a [`NetworkServerHandler`] to send a registered message to
+/// clients.
+pub struct SendToClient {
+    recipient: Recipient,
+    channel: ChannelId,
+    message: Box<dyn AnyMessage>,
+}
+
+impl SendToClient {
+    pub fn new<M: NetMessage>(
+        recipient: Recipient,
+        channel: impl Into<ChannelId>,
+        message: M,
+    ) -> Self {
+        Self {
+            recipient,
+            channel: channel.into(),
+            message: Box::new(message),
+        }
+    }
+}
+
+impl Event for SendToClient {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Drives a server from the engine loop: polls and flushes it on every
+/// [`EngineEvent::Update`], or every [`EngineEvent::FixedUpdate`] with
+/// [`with_fixed_update`](Self::with_fixed_update), emits the messages it receives as
+/// [`ClientMessage`](crate::message::ClientMessage) events and sends the
+/// [`SendToClient`] ones.
+///
+/// The server is stopped when the engine stops, notifying every client.
+pub struct NetworkServerHandler {
+    /// Behind a mutex only because transports need not be `Sync`, handlers
+    /// get exclusive access anyway.
+    server: Mutex<Option<ListeningServer>>,
+    emitter: Option<EventEmitter>,
+    fixed_update: bool,
+}
+
+impl NetworkServerHandler {
+    pub fn new(server: ListeningServer) -> Self {
+        Self {
+            server: Mutex::new(Some(server)),
+            emitter: None,
+            fixed_update: false,
+        }
+    }
+
+    /// Polls and flushes the server once per simulation tick rather than
+    /// once per frame, as dedicated servers usually do.
+    pub fn with_fixed_update(mut self) -> Self {
+        self.fixed_update = true;
+        self
+    }
+
+    /// The server, until the engine stops.
+    pub fn server(&mut self) -> Option<&mut ListeningServer> {
+        self.slot().as_mut()
+    }
+
+    fn slot(&mut self) -> &mut Option<ListeningServer> {
+        self.server.get_mut().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn update(server: &mut ListeningServer, emitter: &EventEmitter) {
+        for event in server.poll() {
+            match event {
+                ServerEvent::Connected(id) => {
+                    emitter.emit(NetworkServerEvent::ClientConnected(id));
+                }
+                ServerEvent::Disconnected(id, reason) => {
+                    emitter.emit(NetworkServerEvent::ClientDisconnected(id, reason));
+                }
+                ServerEvent::Received(id, channel, data) => {
+                    if let Err(err) = server.messages().dispatch_from_client(id, &data, emitter) {
+                        tracing::warn!("dropped message from client {id} on {channel}: {err}");
+                    }
+                }
+            }
+        }
+        server.flush();
+    }
+
+    fn send(server: &mut ListeningServer, request: &SendToClient) -> Result<(), Error> {
+        let data = request.message.encode(server.messages())?;
+        match request.recipient {
+            Recipient::Client(id) => {
+                server.send(id, request.channel, &data)?;
+            }
+            Recipient::All => {
+                let ids: Vec<ClientId> = server.clients().map(|entry| entry.id()).collect();
+                for id in ids {
+                    // One client failing must not keep the others from it
+                    if let Err(err) = server.send(id, request.channel, &data) {
+                        tracing::warn!("failed to send message to client {id}: {err}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventHandler for NetworkServerHandler {
+    fn handle(&mut self, event: &EventBox) -> bool {
+        let emitter = self.emitter.clone();
+        let fixed_update = self.fixed_update;
+        let Some(server) = self.server() else {
+            return false;
+        };
+
+        if let Some(request) = event.downcast_ref::<SendToClient>() {
+            if let Err(err) = Self::send(server, request) {
+                tracing::warn!("failed to send message to {:?}: {err}", request.recipient);
+            }
+            return false;
+        }
+
+        match event.downcast_ref::<EngineEvent>() {
+            Some(EngineEvent::Update) if !fixed_update => {
+                if let Some(emitter) = &emitter {
+                    Self::update(server, emitter);
+                }
+            }
+            Some(EngineEvent::FixedUpdate { .. }) if fixed_update => {
+                if let Some(emitter) = &emitter {
+                    Self::update(server, emitter);
+                }
+            }
+            Some(EngineEvent::Stopping) => {
+                if let Some(server) = self.slot().take() {
+                    server.stop();
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn attach(&mut self, emitter: EventEmitter) {
+        self.emitter = Some(emitter);
+    }
+}
@@ -1,8 +1,42 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::Instant,
+};
 
 use thiserror::Error;
 
-use crate::socket::{self, socket_poll_from, socket_send_to};
+use crate::{
+    conditioner::{ConditionedTransport, LinkConditionerConfig},
+    connection::{ConnectionConfig, DisconnectReason, DISCONNECT_PACKET_COUNT},
+    message::{self, MessageRegistry, NetMessage},
+    protocol::{
+        self,
+        body::PacketBody,
+        buffer::BufferPool,
+        channel::ChannelId,
+        handshake::{negotiate_version, RejectReason},
+        packet::{Compression, PROTOCOL_ID},
+        security::{
+            generate_handshake_nonce, unix_time, ChallengeTag, HandshakeNonce, Key, PacketCipher,
+            PrivateToken, SealedToken,
+        },
+    },
+    socket::{self, socket_recv_from, socket_send_to},
+    transport::{Transport, UdpTransport},
+};
+
+mod clients;
+mod handler;
+
+pub use clients::{ClientEntry, ClientId};
+pub use handler::{NetworkServerEvent, NetworkServerHandler, Recipient, SendToClient};
+
+/// Default amount of clients a server accepts at the same time.
+pub const DEFAULT_MAX_CLIENTS: usize = 32;
+/// Maximum amount of handshakes tracked at once, further requests are ignored
+/// until older ones complete or expire.
+pub const MAX_PENDING_HANDSHAKES: usize = 256;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -10,44 +44,555 @@ pub enum Error {
     SocketBindFailed(#[from] std::io::Error),
     #[error("failed to send data: {0}")]
     SendFailed(String),
+    #[error("client {0} is not connected")]
+    NotConnected(ClientId),
     #[error("socket error: {0}")]
     SocketError(#[from] socket::Error),
+    #[error("protocol error: {0}")]
+    ProtocolError(#[from] protocol::Error),
+    #[error("message error: {0}")]
+    MessageError(#[from] message::Error),
+}
+
+impl From<protocol::packet::Error> for Error {
+    fn from(err: protocol::packet::Error) -> Self {
+        Error::ProtocolError(err.into())
+    }
+}
+
+impl From<protocol::channel::Error> for Error {
+    fn from(err: protocol::channel::Error) -> Self {
+        Error::ProtocolError(err.into())
+    }
 }
 
 pub fn create_server() -> StoppedServer {
     StoppedServer::default()
 }
 
-#[derive(Default)]
-pub struct StoppedServer {}
+pub struct StoppedServer {
+    max_clients: usize,
+    config: ConnectionConfig,
+    conditioner: Option<LinkConditionerConfig>,
+    private_key: Option<Key>,
+    messages: MessageRegistry,
+}
 
 impl StoppedServer {
+    pub fn with_connection_config(mut self, config: ConnectionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets how many clients may be connected at the same time, further
+    /// connection requests are rejected with [`RejectReason::ServerFull`].
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Simulates bad network conditions on the datagrams sent and received
+    /// by the server.
+    pub fn with_link_conditioner(mut self, conditioner: LinkConditionerConfig) -> Self {
+        self.conditioner = Some(conditioner);
+        self
+    }
+
+    /// Only accepts clients with a connect token sealed with the given key,
+    /// and encrypts every packet of their connection.
+    ///
+    /// Each token is only accepted once until it expires.
+    pub fn with_private_key(mut self, private_key: Key) -> Self {
+        self.private_key = Some(private_key);
+        self
+    }
+
+    /// Sets the message types exchanged with clients, see
+    /// [`ListeningServer::send_message`].
+    pub fn with_message_registry(mut self, messages: MessageRegistry) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Binds a UDP socket and starts accepting clients.
     pub fn listen(self, addr: SocketAddr) -> Result<ListeningServer, Error> {
-        let socket = UdpSocket::bind(addr)?;
-        socket.set_nonblocking(true)?;
+        Ok(self.listen_with_transport(UdpTransport::bind(addr)?))
+    }
 
-        Ok(ListeningServer { socket })
+    /// Starts accepting clients over the given transport.
+    pub fn listen_with_transport(self, transport: impl Transport + 'static) -> ListeningServer {
+        let transport: Box<dyn Transport> = match self.conditioner.clone() {
+            Some(conditioner) => Box::new(ConditionedTransport::new(transport, conditioner)),
+            None => Box::new(transport),
+        };
+
+        ListeningServer {
+            transport,
+            max_clients: self.max_clients,
+            config: self.config,
+            conditioner: self.conditioner,
+            private_key: self.private_key,
+            messages: self.messages,
+            pending: HashMap::new(),
+            ciphers: HashMap::new(),
+            used_tokens: HashMap::new(),
+            clients: BTreeMap::new(),
+            addrs: HashMap::new(),
+            next_id: 0,
+            events: Vec::new(),
+            pool: BufferPool::default(),
+        }
     }
 }
 
+impl Default for StoppedServer {
+    fn default() -> Self {
+        Self {
+            max_clients: DEFAULT_MAX_CLIENTS,
+            config: ConnectionConfig::default(),
+            conditioner: None,
+            private_key: None,
+            messages: MessageRegistry::default(),
+        }
+    }
+}
+
+/// Handshake in progress, waiting for the client to echo the challenge.
+struct PendingClient {
+    token: u64,
+    /// Parts of the connection keys picked by the client and the server.
+    client_nonce: HandshakeNonce,
+    nonce: HandshakeNonce,
+    /// Authenticates the challenge for secure clients.
+    tag: Option<ChallengeTag>,
+    version: u16,
+    user_id: Option<u64>,
+    started_at: Instant,
+}
+
+/// Something that happened on a [`ListeningServer`], returned by
+/// [`ListeningServer::poll`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    Connected(ClientId),
+    Disconnected(ClientId, DisconnectReason),
+    Received(ClientId, ChannelId, Vec<u8>),
+}
+
 pub struct ListeningServer {
-    socket: UdpSocket,
+    transport: Box<dyn Transport>,
+    max_clients: usize,
+    config: ConnectionConfig,
+    conditioner: Option<LinkConditionerConfig>,
+    private_key: Option<Key>,
+    messages: MessageRegistry,
+    pending: HashMap<SocketAddr, PendingClient>,
+    /// Ciphers of the secure clients, pending or connected.
+    ciphers: HashMap<SocketAddr, PacketCipher>,
+    /// Expiry of the connect tokens already used, by nonce.
+    used_tokens: HashMap<[u8; 24], u64>,
+    clients: BTreeMap<ClientId, ClientEntry>,
+    addrs: HashMap<SocketAddr, ClientId>,
+    next_id: u64,
+    /// Events produced outside of polling, returned by the next poll.
+    events: Vec<ServerEvent>,
+    pool: BufferPool,
 }
 
 impl ListeningServer {
-    pub fn stop(self) -> StoppedServer {
-        StoppedServer {}
+    /// Stops listening, notifying every connected client.
+    pub fn stop(mut self) -> StoppedServer {
+        let addrs: Vec<_> = self.addrs.keys().copied().collect();
+        for addr in addrs {
+            self.send_disconnect(addr, DisconnectReason::ServerStopped);
+        }
+
+        StoppedServer {
+            max_clients: self.max_clients,
+            config: self.config,
+            conditioner: self.conditioner,
+            private_key: self.private_key,
+            messages: self.messages,
+        }
+    }
+
+    /// Queues the given bytes for a connected client on a channel, returning
+    /// how many were queued.
+    ///
+    /// Nothing is sent until the next [`flush`](Self::flush), which packs
+    /// every queued message into as few datagrams as possible.
+    pub fn send(
+        &mut self,
+        client: ClientId,
+        channel: impl Into<ChannelId>,
+        buf: &[u8],
+    ) -> Result<usize, Error> {
+        let entry = self
+            .clients
+            .get_mut(&client)
+            .ok_or(Error::NotConnected(client))?;
+
+        entry.connection_mut().send(channel.into(), buf)?;
+        Ok(buf.len())
     }
 
-    pub fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize, Error> {
-        Ok(socket_send_to(&self.socket, buf, addr)?)
+    /// Queues a registered message for a connected client on a channel,
+    /// returning how many bytes were queued.
+    pub fn send_message<M: NetMessage>(
+        &mut self,
+        client: ClientId,
+        channel: impl Into<ChannelId>,
+        message: &M,
+    ) -> Result<usize, Error> {
+        let data = self.messages.encode(message)?;
+        self.send(client, channel, &data)
     }
 
-    pub fn poll(&self) -> Vec<(SocketAddr, Vec<u8>)> {
-        socket_poll_from(&self.socket)
+    /// Sends the queued messages, unacknowledged reliable messages and the
+    /// acknowledgement of received packets to every client, or a heartbeat
+    /// to the idle ones.
+    ///
+    /// Send failures are ignored, what was lost is resent like any dropped
+    /// datagram.
+    pub fn flush(&mut self) {
+        let now = Instant::now();
+        let mut buf = self.pool.take();
+        for entry in self.clients.values_mut() {
+            let addr = entry.addr();
+            entry
+                .connection_mut()
+                .write_packets(now, &mut buf, |packet| {
+                    let _ = send_packet(self.transport.as_mut(), &mut self.ciphers, packet, addr);
+                });
+        }
+        self.pool.give(buf);
+    }
+
+    /// Answers handshakes, drops timed out clients and returns what happened
+    /// since the last poll.
+    pub fn poll(&mut self) -> Vec<ServerEvent> {
+        let now = Instant::now();
+        let handshake_timeout = self.config.handshake_timeout();
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.started_at) < handshake_timeout);
+        self.ciphers
+            .retain(|addr, _| self.pending.contains_key(addr) || self.addrs.contains_key(addr));
+
+        let mut events = std::mem::take(&mut self.events);
+        let mut buf = self.pool.take();
+        while let Some(addr) = socket_recv_from(self.transport.as_mut(), &mut buf) {
+            let len = buf.len();
+            let Some(body) = self.open(addr, &mut buf) else {
+                continue;
+            };
+
+            if let Some(entry) = self
+                .addrs
+                .get(&addr)
+                .and_then(|id| self.clients.get_mut(id))
+            {
+                entry.connection_mut().mark_received(len, now);
+            }
+
+            match body {
+                PacketBody::Data { header, messages } => {
+                    if let Some(entry) = self
+                        .addrs
+                        .get(&addr)
+                        .and_then(|id| self.clients.get_mut(id))
+                    {
+                        entry.connection_mut().process(header, messages, now);
+                        let id = entry.id();
+                        events.extend(
+                            entry
+                                .connection_mut()
+                                .drain_received()
+                                .map(|(channel, data)| ServerEvent::Received(id, channel, data)),
+                        );
+                    }
+                }
+                PacketBody::Disconnect(_) => {
+                    if let Some(&id) = self.addrs.get(&addr) {
+                        self.remove(id);
+                        events.push(ServerEvent::Disconnected(id, DisconnectReason::ClientLeft));
+                    }
+                }
+                PacketBody::ConnectRequest {
+                    protocol_id,
+                    version,
+                    token,
+                    nonce,
+                } => self.handle_connect_request(addr, protocol_id, version, token, nonce, now),
+                PacketBody::ChallengeResponse { token } => {
+                    if let Some(id) = self.handle_challenge_response(addr, token, now) {
+                        events.push(ServerEvent::Connected(id));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.pool.give(buf);
+
+        let timed_out: Vec<ClientId> = self
+            .clients
+            .values()
+            .filter(|entry| entry.connection().is_timed_out(now))
+            .map(ClientEntry::id)
+            .collect();
+        for id in timed_out {
+            self.remove(id);
+            events.push(ServerEvent::Disconnected(id, DisconnectReason::TimedOut));
+        }
+
+        events
+    }
+
+    /// Kicks a client, a [`ServerEvent::Disconnected`] is returned by the next
+    /// poll.
+    pub fn disconnect(&mut self, client: ClientId) -> Option<ClientEntry> {
+        let addr = self.clients.get(&client)?.addr();
+        self.send_disconnect(addr, DisconnectReason::Kicked);
+        let entry = self.remove(client)?;
+        self.events
+            .push(ServerEvent::Disconnected(client, DisconnectReason::Kicked));
+        Some(entry)
     }
 
     pub fn addr(&self) -> SocketAddr {
-        self.socket.local_addr().unwrap()
+        self.transport.local_addr()
+    }
+
+    pub fn client(&self, client: ClientId) -> Option<&ClientEntry> {
+        self.clients.get(&client)
+    }
+
+    /// Every connected client, ordered by id.
+    pub fn clients(&self) -> impl Iterator<Item = &ClientEntry> {
+        self.clients.values()
+    }
+
+    /// Message types exchanged with clients, dispatching the received ones
+    /// as events.
+    pub fn messages(&self) -> &MessageRegistry {
+        &self.messages
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    fn handle_connect_request(
+        &mut self,
+        addr: SocketAddr,
+        protocol_id: [u8; 8],
+        version: u16,
+        token: Option<SealedToken>,
+        client_nonce: HandshakeNonce,
+        now: Instant,
+    ) {
+        // The accept may have been lost
+        if let Some(version) = self.connected_version(addr) {
+            self.reply(addr, PacketBody::Accepted { version });
+            return;
+        }
+        // So may the challenge
+        if let Some(pending) = self.pending.get(&addr) {
+            if pending.client_nonce == client_nonce {
+                let challenge = PacketBody::Challenge {
+                    token: pending.token,
+                    nonce: pending.nonce,
+                    tag: pending.tag,
+                };
+                self.reply_unencrypted(addr, challenge);
+                return;
+            }
+            // The client started over
+            self.pending.remove(&addr);
+            self.ciphers.remove(&addr);
+        }
+
+        if protocol_id != *PROTOCOL_ID {
+            self.reply(addr, PacketBody::Rejected(RejectReason::ProtocolMismatch));
+            return;
+        }
+        let opened = match self.private_key {
+            Some(private_key) => match self.validate_token(token.as_ref(), &private_key) {
+                Some(opened) => Some(opened),
+                None => {
+                    self.reply(
+                        addr,
+                        PacketBody::Rejected(RejectReason::InvalidConnectToken),
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+        let version = match negotiate_version(version) {
+            Ok(version) => version,
+            Err(reason) => {
+                self.reply(addr, PacketBody::Rejected(reason));
+                return;
+            }
+        };
+        if self.clients.len() >= self.max_clients {
+            self.reply(addr, PacketBody::Rejected(RejectReason::ServerFull));
+            return;
+        }
+
+        if self.pending.len() >= MAX_PENDING_HANDSHAKES {
+            return;
+        }
+        let challenge: u64 = rand::random();
+        let nonce = generate_handshake_nonce();
+        let pending = PendingClient {
+            token: challenge,
+            client_nonce,
+            nonce,
+            tag: opened
+                .as_ref()
+                .map(|opened| opened.challenge_tag(&client_nonce, &nonce, challenge)),
+            version,
+            user_id: opened.as_ref().map(|opened| opened.user_id),
+            started_at: now,
+        };
+        if let (Some(opened), Some(token)) = (opened, token) {
+            self.used_tokens.insert(token.nonce(), token.expires_at());
+            self.ciphers
+                .insert(addr, opened.server_cipher(&client_nonce, &pending.nonce));
+        }
+
+        // The client needs the nonce to derive the keys, sent unencrypted
+        let challenge = PacketBody::Challenge {
+            token: pending.token,
+            nonce: pending.nonce,
+            tag: pending.tag,
+        };
+        self.pending.insert(addr, pending);
+        self.reply_unencrypted(addr, challenge);
+    }
+
+    fn handle_challenge_response(
+        &mut self,
+        addr: SocketAddr,
+        token: u64,
+        now: Instant,
+    ) -> Option<ClientId> {
+        if let Some(version) = self.connected_version(addr) {
+            self.reply(addr, PacketBody::Accepted { version });
+            return None;
+        }
+
+        let pending = self.pending.get(&addr)?;
+        if pending.token != token {
+            return None;
+        }
+        if self.clients.len() >= self.max_clients {
+            self.pending.remove(&addr);
+            self.reply(addr, PacketBody::Rejected(RejectReason::ServerFull));
+            return None;
+        }
+
+        let version = pending.version;
+        let user_id = pending.user_id;
+        self.pending.remove(&addr);
+
+        let id = ClientId::new(self.next_id);
+        self.next_id += 1;
+        self.clients.insert(
+            id,
+            ClientEntry::new(id, addr, version, user_id, self.config.clone(), now),
+        );
+        self.addrs.insert(addr, id);
+
+        self.reply(addr, PacketBody::Accepted { version });
+        Some(id)
+    }
+
+    fn remove(&mut self, client: ClientId) -> Option<ClientEntry> {
+        let entry = self.clients.remove(&client)?;
+        self.addrs.remove(&entry.addr());
+        self.ciphers.remove(&entry.addr());
+        Some(entry)
+    }
+
+    fn send_disconnect(&mut self, addr: SocketAddr, reason: DisconnectReason) {
+        for _ in 0..DISCONNECT_PACKET_COUNT {
+            self.reply(addr, PacketBody::Disconnect(reason));
+        }
+    }
+
+    fn connected_version(&self, addr: SocketAddr) -> Option<u16> {
+        let id = self.addrs.get(&addr)?;
+        self.clients.get(id).map(ClientEntry::protocol_version)
+    }
+
+    /// Sends a handshake or disconnect packet, failures are ignored since
+    /// those are sent again anyway.
+    fn reply(&mut self, addr: SocketAddr, body: PacketBody) {
+        let mut buf = self.pool.take();
+        if body.encode_into(Compression::None, &mut buf).is_ok() {
+            let _ = send_packet(self.transport.as_mut(), &mut self.ciphers, &mut buf, addr);
+        }
+        self.pool.give(buf);
+    }
+
+    fn reply_unencrypted(&mut self, addr: SocketAddr, body: PacketBody) {
+        let mut buf = self.pool.take();
+        if body.encode_into(Compression::None, &mut buf).is_ok() {
+            let _ = socket_send_to(self.transport.as_mut(), &buf, addr);
+        }
+        self.pool.give(buf);
+    }
+
+    /// Opens a connect token that was not used yet.
+    fn validate_token(
+        &mut self,
+        token: Option<&SealedToken>,
+        private_key: &Key,
+    ) -> Option<PrivateToken> {
+        let token = token?;
+        let now = unix_time();
+        self.used_tokens.retain(|_, expires_at| *expires_at > now);
+        if self.used_tokens.contains_key(&token.nonce()) {
+            return None;
+        }
+        token.open(private_key)
+    }
+
+    /// Decrypts in place and decodes a datagram, secure servers only accept
+    /// connect requests unencrypted.
+    fn open(&mut self, addr: SocketAddr, data: &mut [u8]) -> Option<PacketBody> {
+        if let Some(packet) = self
+            .ciphers
+            .get_mut(&addr)
+            .and_then(|cipher| cipher.open(data))
+        {
+            return PacketBody::decode(packet).ok();
+        }
+
+        let body = PacketBody::decode(data).ok()?;
+        if self.private_key.is_some() && !matches!(body, PacketBody::ConnectRequest { .. }) {
+            return None;
+        }
+        Some(body)
+    }
+}
+
+/// Sends an encoded packet, encrypted in place first when the client at
+/// `addr` is secure.
+fn send_packet(
+    transport: &mut dyn Transport,
+    ciphers: &mut HashMap<SocketAddr, PacketCipher>,
+    buf: &mut Vec<u8>,
+    addr: SocketAddr,
+) -> Result<usize, Error> {
+    if let Some(cipher) = ciphers.get_mut(&addr) {
+        cipher.seal(buf).map_err(protocol::Error::from)?;
     }
+    Ok(socket_send_to(transport, buf, addr)?)
 }
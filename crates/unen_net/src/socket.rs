@@ -1,8 +1,8 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 
 use thiserror::Error;
 
-use crate::protocol::packet::PACKET_MAX_SIZE;
+use crate::{protocol::buffer::DATAGRAM_MAX_SIZE, transport::Transport};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -10,32 +10,28 @@ pub enum Error {
     SendFailed(String),
 }
 
-pub fn socket_send_to(socket: &UdpSocket, buf: &[u8], addr: SocketAddr) -> Result<usize, Error> {
-    socket
+pub fn socket_send_to(
+    transport: &mut dyn Transport,
+    buf: &[u8],
+    addr: SocketAddr,
+) -> Result<usize, Error> {
+    transport
         .send_to(buf, addr)
         .map_err(|err| Error::SendFailed(err.to_string()))
 }
 
-pub fn socket_poll_from(socket: &UdpSocket) -> Vec<(SocketAddr, Vec<u8>)> {
-    let mut received = Vec::new();
-    let mut buf = [0u8; PACKET_MAX_SIZE];
-    while let Ok((len, addr)) = socket.recv_from(&mut buf) {
-        received.push((addr, buf[..len].to_vec()));
+/// Receives the next datagram into `buf`, resized to its length, returning
+/// its sender.
+pub fn socket_recv_from(transport: &mut dyn Transport, buf: &mut Vec<u8>) -> Option<SocketAddr> {
+    buf.resize(DATAGRAM_MAX_SIZE, 0);
+    match transport.recv_from(buf) {
+        Ok((len, addr)) => {
+            buf.truncate(len);
+            Some(addr)
+        }
+        Err(_) => {
+            buf.clear();
+            None
+        }
     }
-    received
-}
-
-pub fn socket_send(socket: &UdpSocket, buf: &[u8]) -> Result<usize, Error> {
-    socket
-        .send(buf)
-        .map_err(|err| Error::SendFailed(err.to_string()))
-}
-
-pub fn socket_poll(socket: &UdpSocket) -> Vec<Vec<u8>> {
-    let mut received = Vec::new();
-    let mut buf = [0u8; PACKET_MAX_SIZE];
-    while let Ok(len) = socket.recv(&mut buf) {
-        received.push(buf[..len].to_owned());
-    }
-    received
 }
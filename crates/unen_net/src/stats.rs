@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// How long traffic is accumulated before the per-second rates are updated.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Network statistics of a connection, meant to be rendered in a netgraph.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkStats {
+    /// Smoothed round-trip time, derived from acknowledgements.
+    pub rtt: Duration,
+    /// Smoothed variation of the round-trip time.
+    pub jitter: Duration,
+    /// Smoothed percentage of sent packets that were never acknowledged.
+    pub packet_loss: f32,
+    pub sent_bytes_per_sec: f32,
+    pub sent_packets_per_sec: f32,
+    pub received_bytes_per_sec: f32,
+    pub received_packets_per_sec: f32,
+    /// Total amount of reliable messages sent again.
+    pub resent_messages: u64,
+}
+
+/// Traffic rate in one direction, over the last complete window.
+#[derive(Debug, Clone)]
+pub(crate) struct Throughput {
+    window_start: Instant,
+    bytes: usize,
+    packets: usize,
+    bytes_per_sec: f32,
+    packets_per_sec: f32,
+}
+
+impl Throughput {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            bytes: 0,
+            packets: 0,
+            bytes_per_sec: 0.0,
+            packets_per_sec: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, bytes: usize, now: Instant) {
+        self.update(now);
+        self.bytes += bytes;
+        self.packets += 1;
+    }
+
+    /// Computes the rates once the current window is over.
+    pub fn update(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < THROUGHPUT_WINDOW {
+            return;
+        }
+
+        (self.bytes_per_sec, self.packets_per_sec) = self.rates(now);
+        let windows = elapsed.as_nanos() / THROUGHPUT_WINDOW.as_nanos();
+        self.window_start += THROUGHPUT_WINDOW * windows as u32;
+        self.bytes = 0;
+        self.packets = 0;
+    }
+
+    /// Bytes and packets per second over the last window complete at `now`,
+    /// which is empty once traffic stopped for a whole window.
+    pub fn rates(&self, now: Instant) -> (f32, f32) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < THROUGHPUT_WINDOW {
+            return (self.bytes_per_sec, self.packets_per_sec);
+        }
+        // Traffic is only counted in the current window, the ones after it
+        // had none
+        if elapsed >= THROUGHPUT_WINDOW * 2 {
+            return (0.0, 0.0);
+        }
+
+        let secs = THROUGHPUT_WINDOW.as_secs_f32();
+        (self.bytes as f32 / secs, self.packets as f32 / secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(start);
+        for i in 0..10 {
+            throughput.record(100, start + Duration::from_millis(i * 50));
+        }
+        assert_eq!(
+            throughput.rates(start + Duration::from_millis(500)),
+            (0.0, 0.0),
+            "should not report a rate before the window is over"
+        );
+        assert_eq!(
+            throughput.rates(start + Duration::from_millis(1500)),
+            (1000.0, 10.0),
+            "should average the traffic over the window"
+        );
+
+        throughput.record(100, start + Duration::from_millis(1500));
+        assert_eq!(
+            throughput.rates(start + Duration::from_millis(1900)),
+            (1000.0, 10.0),
+            "should keep the rates of the last window"
+        );
+        assert_eq!(
+            throughput.rates(start + Duration::from_millis(2500)),
+            (100.0, 1.0),
+            "should not average over the gap before the traffic"
+        );
+        assert_eq!(
+            throughput.rates(start + Duration::from_secs(5)),
+            (0.0, 0.0),
+            "should age out the rates once the traffic stopped"
+        );
+    }
+}
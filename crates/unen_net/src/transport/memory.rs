@@ -0,0 +1,118 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+};
+
+use super::Transport;
+
+/// Source of the made up ports of memory transports, so that every one has
+/// a distinct address.
+static NEXT_PORT: AtomicU16 = AtomicU16::new(1);
+
+/// Transport delivering datagrams through memory to its peer, for tests that
+/// should not depend on the network.
+///
+/// Datagrams sent to any address other than the peer are dropped, like
+/// unroutable UDP datagrams.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    peer: SocketAddr,
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    /// Creates two transports connected to each other.
+    pub fn pair() -> (Self, Self) {
+        let (a_addr, b_addr) = (next_addr(), next_addr());
+        let (a_sender, b_receiver) = mpsc::channel();
+        let (b_sender, a_receiver) = mpsc::channel();
+
+        (
+            Self {
+                addr: a_addr,
+                peer: b_addr,
+                sender: a_sender,
+                receiver: a_receiver,
+            },
+            Self {
+                addr: b_addr,
+                peer: a_addr,
+                sender: b_sender,
+                receiver: b_receiver,
+            },
+        )
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if addr == self.peer {
+            // A dropped peer loses the datagram, like a closed UDP port
+            let _ = self.sender.send(buf.to_vec());
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let data = self
+            .receiver
+            .try_recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+
+        // Like UDP, what does not fit the buffer is discarded
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, self.peer))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+fn next_addr() -> SocketAddr {
+    let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let mut buf = [0u8; 8];
+
+        assert_eq!(
+            b.recv_from(&mut buf).map_err(|err| err.kind()),
+            Err(io::ErrorKind::WouldBlock),
+            "should not block when nothing was sent"
+        );
+
+        a.send_to(b"hello", b.local_addr())
+            .expect("should be able to send");
+        a.send_to(b"lost", a.local_addr())
+            .expect("should be able to send");
+        assert_eq!(
+            b.recv_from(&mut buf).ok(),
+            Some((5, a.local_addr())),
+            "should receive from the peer"
+        );
+        assert_eq!(&buf[..5], b"hello", "should receive the datagram");
+        assert!(
+            b.recv_from(&mut buf).is_err(),
+            "should drop datagrams sent elsewhere"
+        );
+    }
+}
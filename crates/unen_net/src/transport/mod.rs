@@ -0,0 +1,37 @@
+use std::{io, net::SocketAddr};
+
+mod memory;
+mod udp;
+
+pub use memory::MemoryTransport;
+pub use udp::UdpTransport;
+
+/// Unreliable datagram transport the protocol runs on.
+///
+/// Implementations must not block: [`recv_from`](Self::recv_from) returns an
+/// [`io::ErrorKind::WouldBlock`] error when nothing was received, like a
+/// nonblocking [`std::net::UdpSocket`].
+pub trait Transport: Send {
+    /// Sends a datagram, returning how many bytes were sent.
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Receives the next datagram into `buf`, returning its length and
+    /// sender.
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    fn local_addr(&self) -> SocketAddr;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        (**self).send_to(buf, addr)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        (**self).recv_from(buf)
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        (**self).local_addr()
+    }
+}
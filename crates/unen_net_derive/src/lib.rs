@@ -0,0 +1,53 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitInt};
+
+/// Implements `NetMessage`, with an id derived from the type name unless one
+/// is given with `#[net_message(id = ...)]`.
+#[proc_macro_derive(NetMessage, attributes(net_message))]
+pub fn derive_net_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let id = match message_id(&input) {
+        Ok(id) => id,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics ::unen_net::message::NetMessage for #name #ty_generics #where_clause {
+            const ID: ::unen_net::message::MessageId = ::unen_net::message::MessageId::new(#id);
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+fn message_id(input: &DeriveInput) -> syn::Result<u32> {
+    let mut id = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("net_message"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `id`"))
+            }
+        })?;
+    }
+
+    Ok(id.unwrap_or_else(|| fnv1a(&input.ident.to_string())))
+}
+
+/// FNV-1a hash of the type name, stable across builds and platforms unlike
+/// `TypeId`.
+fn fnv1a(name: &str) -> u32 {
+    name.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
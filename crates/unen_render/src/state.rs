@@ -108,6 +108,7 @@ impl RendererState {
     }
 
     pub fn render(&mut self) {
+        let _render = tracing::trace_span!("render").entered();
         if !self.is_configured {
             log::trace!("Skipping render - surface not configured yet");
             return;
@@ -155,6 +156,7 @@ impl RendererState {
             });
         }
 
+        let _submit = tracing::trace_span!("submit").entered();
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
     }
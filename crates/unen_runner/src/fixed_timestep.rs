@@ -0,0 +1,110 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// Default interval of the simulation ticks, 60 Hz.
+pub const DEFAULT_FIXED_TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Default amount of ticks run in a single frame to catch up.
+pub const DEFAULT_MAX_STEPS: u32 = 8;
+
+/// Turns the variable time between frames into a fixed number of simulation
+/// ticks, for runners to emit as [`EngineEvent::FixedUpdate`].
+///
+/// [`EngineEvent::FixedUpdate`]: unen_event::prelude::EngineEvent::FixedUpdate
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    interval: Duration,
+    max_steps: u32,
+    accumulated: Duration,
+    last: Option<Instant>,
+    next_tick: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_steps: DEFAULT_MAX_STEPS,
+            accumulated: Duration::ZERO,
+            last: None,
+            next_tick: 0,
+        }
+    }
+
+    /// Sets how many ticks may run in a single frame, the time past that is
+    /// dropped so that a slow frame does not snowball into slower ones.
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the ticks to run for a frame starting at `now`.
+    pub fn advance(&mut self, now: Instant) -> Range<u32> {
+        if let Some(last) = self.last {
+            self.accumulated += now.saturating_duration_since(last);
+        }
+        self.last = Some(now);
+
+        let mut steps = 0;
+        while self.accumulated >= self.interval && steps < self.max_steps {
+            self.accumulated -= self.interval;
+            steps += 1;
+        }
+        if steps == self.max_steps {
+            self.accumulated = Duration::ZERO;
+        }
+
+        let first = self.next_tick;
+        self.next_tick += steps;
+        first..self.next_tick
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(DEFAULT_FIXED_TIMESTEP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let interval = Duration::from_millis(10);
+        let mut timestep = FixedTimestep::new(interval).with_max_steps(4);
+        let start = Instant::now();
+
+        assert_eq!(
+            timestep.advance(start),
+            0..0,
+            "should not tick on the first frame"
+        );
+        assert_eq!(
+            timestep.advance(start + Duration::from_millis(25)),
+            0..2,
+            "should run every elapsed tick"
+        );
+        assert_eq!(
+            timestep.advance(start + Duration::from_millis(30)),
+            2..3,
+            "should carry the remaining time over"
+        );
+        assert_eq!(
+            timestep.advance(start + Duration::from_secs(1)),
+            3..7,
+            "should not run more than the maximum steps"
+        );
+        assert_eq!(
+            timestep.advance(start + Duration::from_secs(1)),
+            7..7,
+            "should drop the time past the maximum steps"
+        );
+    }
+}
@@ -1,8 +1,10 @@
+mod fixed_timestep;
 mod minimal_runner;
 mod runner;
 
 pub mod prelude {
     pub use crate::{
+        fixed_timestep::FixedTimestep, fixed_timestep::DEFAULT_FIXED_TIMESTEP,
         minimal_runner::MininalRunner, runner::Runner, runner::RunnerBox, runner::RunnerData,
         runner::SharedRunnerData,
     };
@@ -4,7 +4,7 @@ use std::{
         Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use signal_hook::{
@@ -13,10 +13,22 @@ use signal_hook::{
 };
 use unen_event::prelude::EngineEvent;
 
-use crate::runner::{Runner, SharedRunnerData};
+use crate::{
+    fixed_timestep::FixedTimestep,
+    runner::{Runner, SharedRunnerData},
+};
 
 pub struct MininalRunner {
     term: Arc<AtomicBool>,
+    timestep: FixedTimestep,
+}
+
+impl MininalRunner {
+    /// Sets the interval of the [`EngineEvent::FixedUpdate`] ticks.
+    pub fn with_fixed_timestep(mut self, interval: Duration) -> Self {
+        self.timestep = FixedTimestep::new(interval);
+        self
+    }
 }
 
 impl Runner for MininalRunner {
@@ -24,9 +36,20 @@ impl Runner for MininalRunner {
         let _ = flag::register(SIGINT, Arc::clone(&self.term));
         let _ = flag::register(SIGTERM, Arc::clone(&self.term));
 
-        while !self.term.load(Ordering::Relaxed) {
-            data.lock().unwrap().event_emitter.emit(EngineEvent::Update);
-            data.lock().unwrap().event_manager.step();
+        while !self.term.load(Ordering::Relaxed)
+            && !data.lock().unwrap().event_manager.is_shutdown_requested()
+        {
+            {
+                let _frame = tracing::trace_span!("frame").entered();
+                for tick in self.timestep.advance(Instant::now()) {
+                    data.lock()
+                        .unwrap()
+                        .event_emitter
+                        .emit(EngineEvent::FixedUpdate { tick });
+                }
+                data.lock().unwrap().event_emitter.emit(EngineEvent::Update);
+                data.lock().unwrap().event_manager.step();
+            }
             thread::sleep(Duration::from_millis(1));
         }
 
@@ -39,6 +62,7 @@ impl Default for MininalRunner {
     fn default() -> Self {
         Self {
             term: Arc::new(AtomicBool::new(false)),
+            timestep: FixedTimestep::default(),
         }
     }
 }
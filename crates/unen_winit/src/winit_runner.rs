@@ -1,7 +1,11 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use unen_runner::prelude::{Runner, SharedRunnerData};
+use unen_event::prelude::EngineEvent;
+use unen_runner::prelude::{FixedTimestep, Runner, SharedRunnerData};
 
 use unen_window::prelude::{SendableWindowHandle, WindowEvent};
 use winit::{
@@ -22,7 +26,6 @@ use winit::event_loop::EventLoopProxy;
 use winit::platform::web::WindowAttributesExtWebSys;
 
 struct State {
-    #[allow(dead_code)]
     window: Arc<Window>,
 }
 
@@ -38,6 +41,7 @@ pub struct WinitRunner {
     proxy: Option<EventLoopProxy<State>>,
     state: Option<State>,
     runner_data: Option<SharedRunnerData>,
+    timestep: FixedTimestep,
 }
 
 impl Runner for WinitRunner {
@@ -58,8 +62,15 @@ impl WinitRunner {
             proxy,
             state: None,
             runner_data: None,
+            timestep: FixedTimestep::default(),
         }
     }
+
+    /// Sets the interval of the [`EngineEvent::FixedUpdate`] ticks.
+    pub fn with_fixed_timestep(mut self, interval: Duration) -> Self {
+        self.timestep = FixedTimestep::new(interval);
+        self
+    }
 }
 
 impl ApplicationHandler<State> for WinitRunner {
@@ -156,8 +167,21 @@ impl ApplicationHandler<State> for WinitRunner {
                 });
             }
             winit::event::WindowEvent::RedrawRequested => {
+                let _frame = tracing::trace_span!("frame").entered();
+                for tick in self.timestep.advance(Instant::now()) {
+                    runner_data
+                        .event_emitter
+                        .emit(EngineEvent::FixedUpdate { tick });
+                }
+                runner_data.event_emitter.emit(EngineEvent::Update);
                 runner_data.event_emitter.emit(WindowEvent::Redraw);
                 runner_data.event_manager.step();
+                if runner_data.event_manager.is_shutdown_requested() {
+                    event_loop.exit();
+                } else if let Some(state) = &self.state {
+                    // Keeps the frames, and the engine updates, coming
+                    state.window.request_redraw();
+                }
             }
             winit::event::WindowEvent::KeyboardInput {
                 event: